@@ -5,5 +5,8 @@ use utils::emu;
 
 fn main() {
     //env::set_var("RUST_BACKTRACE", "full");
-    emu::run().unwrap();
+    if let Err(err) = emu::run() {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 }
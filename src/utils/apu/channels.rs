@@ -0,0 +1,358 @@
+// Sound channels and the units shared between them
+// https://gbdev.io/pandocs/Audio_details.html
+
+const DUTY_TABLE : [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+const NOISE_DIVISORS : [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// disables the channel once the counter runs out (clocked at 256 Hz)
+#[derive(Debug, Default)]
+pub struct LengthCounter {
+    enabled : bool,
+    counter : u16,
+    max     : u16, // 64, or 256 for the wave channel
+}
+
+impl LengthCounter {
+    fn new(max : u16) -> Self {
+        LengthCounter { enabled : false, counter : 0, max }
+    }
+    fn load(&mut self, value : u8) {
+        self.counter = self.max - (value as u16 & (self.max - 1));
+    }
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+    // returns false when the channel must be disabled
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+        true
+    }
+}
+
+// volume envelope (clocked at 64 Hz)
+#[derive(Debug, Default)]
+pub struct Envelope {
+    initial  : u8,
+    increase : bool,
+    period   : u8,
+    volume   : u8,
+    timer    : u8,
+}
+
+impl Envelope {
+    fn write(&mut self, value : u8) {
+        self.initial  = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period   = value & 0x07;
+    }
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer  = self.period;
+    }
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            match self.increase {
+                true  if self.volume < 15 => self.volume += 1,
+                false if self.volume > 0  => self.volume -= 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+// frequency sweep of channel 1 (clocked at 128 Hz)
+#[derive(Debug, Default)]
+pub struct Sweep {
+    period  : u8,
+    negate  : bool,
+    shift   : u8,
+    timer   : u8,
+    shadow  : u16,
+    enabled : bool,
+}
+
+impl Sweep {
+    fn write(&mut self, value : u8) {
+        self.period = (value >> 4) & 0x07;
+        self.negate = value & 0x08 != 0;
+        self.shift  = value & 0x07;
+    }
+    fn reload_timer(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+    // next frequency, None when it overflows
+    fn calculate(&self) -> Option<u16> {
+        let delta = self.shadow >> self.shift;
+        let frequency = match self.negate {
+            true  => self.shadow.wrapping_sub(delta),
+            false => self.shadow + delta,
+        };
+        if frequency > 2047 { None } else { Some(frequency) }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SquareChannel {
+    pub enabled : bool,
+    pub dac     : bool,
+    pub length  : LengthCounter,
+    envelope    : Envelope,
+    sweep       : Option<Sweep>, // only available on channel 1
+    duty        : u8,
+    position    : u8,
+    frequency   : u16,
+    timer       : u32,
+}
+
+impl SquareChannel {
+    pub fn new(with_sweep : bool) -> Self {
+        SquareChannel {
+            length : LengthCounter::new(64),
+            sweep  : if with_sweep { Some(Sweep::default()) } else { None },
+            ..Default::default()
+        }
+    }
+    // register 0 (NR10) is only used by the sweep unit
+    pub fn write(&mut self, reg : u8, value : u8) {
+        match reg {
+            0 => if let Some(sweep) = self.sweep.as_mut() { sweep.write(value) },
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            },
+            2 => {
+                self.envelope.write(value);
+                self.dac = value & 0xF8 != 0;
+                self.enabled &= self.dac;
+            },
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            _ => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            },
+        }
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.length.trigger();
+        self.envelope.trigger();
+        self.timer = (2048 - self.frequency as u32) * 4;
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow = self.frequency;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            if sweep.shift != 0 && sweep.calculate().is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+    pub fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency as u32) * 4;
+            self.position = (self.position + 1) % 8;
+        }
+    }
+    pub fn clock_length(&mut self) {
+        self.enabled &= self.length.clock();
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else { return };
+
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+        match sweep.calculate() {
+            Some(frequency) if sweep.shift != 0 => {
+                sweep.shadow = frequency;
+                self.frequency = frequency;
+                // the new frequency is checked again for an overflow
+                if sweep.calculate().is_none() {
+                    self.enabled = false;
+                }
+            },
+            Some(_) => {},
+            None => self.enabled = false,
+        }
+    }
+    // digital output (0-15)
+    pub fn output(&self) -> u8 {
+        match self.enabled {
+            true  => DUTY_TABLE[self.duty as usize][self.position as usize] * self.envelope.volume,
+            false => 0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WaveChannel {
+    pub enabled : bool,
+    pub dac     : bool,
+    pub length  : LengthCounter,
+    pub ram     : [u8; 16], // 32 4-bit samples
+    volume      : u8,
+    position    : u8,
+    frequency   : u16,
+    timer       : u32,
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        WaveChannel {
+            length : LengthCounter::new(256),
+            ..Default::default()
+        }
+    }
+    pub fn write(&mut self, reg : u8, value : u8) {
+        match reg {
+            0 => {
+                self.dac = value & 0x80 != 0;
+                self.enabled &= self.dac;
+            },
+            1 => self.length.load(value),
+            2 => self.volume = (value >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            _ => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                self.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            },
+        }
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.length.trigger();
+        self.timer = (2048 - self.frequency as u32) * 2;
+        self.position = 0;
+    }
+    pub fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency as u32) * 2;
+            self.position = (self.position + 1) % 32;
+        }
+    }
+    pub fn clock_length(&mut self) {
+        self.enabled &= self.length.clock();
+    }
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let byte = self.ram[(self.position / 2) as usize];
+        let sample = if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0x0F };
+        match self.volume {
+            0 => 0,           // mute
+            shift => sample >> (shift - 1), // 100%, 50%, 25%
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NoiseChannel {
+    pub enabled : bool,
+    pub dac     : bool,
+    pub length  : LengthCounter,
+    envelope    : Envelope,
+    shift       : u8,
+    narrow      : bool, // 7-bit LFSR mode
+    divisor     : u8,
+    lfsr        : u16,
+    timer       : u32,
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        NoiseChannel {
+            length : LengthCounter::new(64),
+            lfsr   : 0x7FFF,
+            ..Default::default()
+        }
+    }
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor as usize] << self.shift
+    }
+    pub fn write(&mut self, reg : u8, value : u8) {
+        match reg {
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.write(value);
+                self.dac = value & 0xF8 != 0;
+                self.enabled &= self.dac;
+            },
+            3 => {
+                self.shift   = value >> 4;
+                self.narrow  = value & 0x08 != 0;
+                self.divisor = value & 0x07;
+            },
+            4 => {
+                self.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            },
+            _ => {}, // 0xFF1F is unused
+        }
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.length.trigger();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+        self.timer = self.period();
+    }
+    pub fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period();
+
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+            self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+            if self.narrow {
+                self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+            }
+        }
+    }
+    pub fn clock_length(&mut self) {
+        self.enabled &= self.length.clock();
+    }
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+    pub fn output(&self) -> u8 {
+        match self.enabled && self.lfsr & 0x01 == 0 {
+            true  => self.envelope.volume,
+            false => 0,
+        }
+    }
+}
@@ -0,0 +1,278 @@
+pub mod channels;
+
+use channels::{
+    SquareChannel,
+    WaveChannel,
+    NoiseChannel,
+};
+use crate::cpu::CPU_CLOCK;
+
+// 0xFF10 - 0xFF14: Channel 1 (square with sweep)
+// 0xFF16 - 0xFF19: Channel 2 (square)
+// 0xFF1A - 0xFF1E: Channel 3 (wave)
+// 0xFF20 - 0xFF23: Channel 4 (noise)
+// 0xFF24 - 0xFF26: Master volume, panning and sound on/off
+// 0xFF30 - 0xFF3F: Wave pattern RAM
+// https://gbdev.io/pandocs/Audio_Registers.html
+
+const FRAME_SEQUENCER_PERIOD : u32 = CPU_CLOCK / 512;
+
+// samples nobody drains are dropped past this much audio, oldest first
+const MAX_BUFFERED_SECONDS : u32 = 1;
+
+// bits that always read back as 1 (0xFF10 - 0xFF2F)
+const READ_MASKS : [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40 - NR44
+    0x00, 0x00, 0x70,             // NR50 - NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // unused
+];
+
+pub struct Apu {
+    powered : bool,       // NR52 bit 7
+    regs    : [u8; 0x20], // last written values, for read back
+
+    square1 : SquareChannel,
+    square2 : SquareChannel,
+    wave    : WaveChannel,
+    noise   : NoiseChannel,
+
+    frame_timer : u32,
+    frame_step  : u8,
+
+    sample_rate  : u32,
+    sample_timer : u32,
+    samples      : Vec<f32>, // interleaved stereo (left, right)
+}
+
+impl Apu {
+    pub fn new(sample_rate : u32) -> Self {
+        Apu {
+            powered : true,
+            regs    : [0u8; 0x20],
+            square1 : SquareChannel::new(true),
+            square2 : SquareChannel::new(false),
+            wave    : WaveChannel::new(),
+            noise   : NoiseChannel::new(),
+            frame_timer  : 0,
+            frame_step   : 0,
+            sample_rate,
+            sample_timer : 0,
+            samples      : Vec::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    /// Drains the interleaved stereo samples produced so far
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn read(&self, addr : u16) -> u8 {
+        match addr {
+            0xFF26 => {
+                0x70 | (self.powered as u8) << 7
+                     | (self.noise.enabled as u8) << 3
+                     | (self.wave.enabled as u8) << 2
+                     | (self.square2.enabled as u8) << 1
+                     | (self.square1.enabled as u8)
+            },
+            0xFF10..=0xFF2F => {
+                let index = (addr - 0xFF10) as usize;
+                self.regs[index] | READ_MASKS[index]
+            },
+            0xFF30..=0xFF3F => self.wave.ram[(addr - 0xFF30) as usize],
+            _ => panic!("Invalid APU address {:#06X}", addr)
+        }
+    }
+    pub fn write(&mut self, addr : u16, value : u8) {
+        match addr {
+            0xFF26 => {
+                let powered = value & 0x80 != 0;
+                if self.powered && !powered { // powering off clears every register
+                    self.power_off();
+                } else if !self.powered && powered {
+                    self.frame_step = 0;
+                }
+                self.powered = powered;
+            },
+            0xFF30..=0xFF3F => self.wave.ram[(addr - 0xFF30) as usize] = value,
+            // registers are read-only while powered off
+            0xFF10..=0xFF2F if !self.powered => {},
+            0xFF10..=0xFF2F => {
+                let index = (addr - 0xFF10) as u8;
+                self.regs[index as usize] = value;
+
+                match index {
+                    0x00..=0x04 => self.square1.write(index, value),
+                    0x05..=0x09 => self.square2.write(index - 0x05, value),
+                    0x0A..=0x0E => self.wave.write(index - 0x0A, value),
+                    0x0F..=0x13 => self.noise.write(index - 0x0F, value),
+                    _ => {} // NR50 and NR51 are read straight from the registers
+                }
+            },
+            _ => panic!("Invalid APU address {:#06X}", addr)
+        }
+    }
+    fn power_off(&mut self) {
+        let wave_ram = self.wave.ram;
+
+        self.regs    = [0u8; 0x20];
+        self.square1 = SquareChannel::new(true);
+        self.square2 = SquareChannel::new(false);
+        self.wave    = WaveChannel::new();
+        self.noise   = NoiseChannel::new();
+
+        self.wave.ram = wave_ram; // wave RAM is not affected
+    }
+
+    pub fn tick(&mut self, cycles : u32) {
+        for _ in 0..cycles {
+            if self.powered {
+                self.frame_timer += 1;
+                if self.frame_timer == FRAME_SEQUENCER_PERIOD {
+                    self.frame_timer = 0;
+                    self.clock_frame_sequencer();
+                }
+                self.square1.tick();
+                self.square2.tick();
+                self.wave.tick();
+                self.noise.tick();
+            }
+            // resample from the cpu clock to the host sample rate
+            self.sample_timer += self.sample_rate;
+            if self.sample_timer >= CPU_CLOCK {
+                self.sample_timer -= CPU_CLOCK;
+                self.push_sample();
+            }
+        }
+    }
+
+    // step 0, 2, 4, 6: length | step 2, 6: sweep | step 7: envelope
+    fn clock_frame_sequencer(&mut self) {
+        if self.frame_step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    fn push_sample(&mut self) {
+        let nr50 = self.regs[0x14];
+        let nr51 = self.regs[0x15];
+
+        // digital output (0-15) scaled to 0.0 - 1.0 when the channel DAC is on
+        let outputs = [
+            if self.square1.dac { self.square1.output() as f32 / 15.0 } else { 0.0 },
+            if self.square2.dac { self.square2.output() as f32 / 15.0 } else { 0.0 },
+            if self.wave.dac    { self.wave.output()    as f32 / 15.0 } else { 0.0 },
+            if self.noise.dac   { self.noise.output()   as f32 / 15.0 } else { 0.0 },
+        ];
+
+        let mut left  = 0.0;
+        let mut right = 0.0;
+        for (channel, output) in outputs.iter().enumerate() {
+            if nr51 & (0x10 << channel) != 0 { left  += output; }
+            if nr51 & (0x01 << channel) != 0 { right += output; }
+        }
+        let left_volume  = ((nr50 >> 4) & 0x07) as f32 + 1.0;
+        let right_volume = (nr50 & 0x07) as f32 + 1.0;
+
+        let capacity = (2 * self.sample_rate * MAX_BUFFERED_SECONDS).max(2) as usize;
+        if self.samples.len() >= capacity {
+            self.samples.drain(..(capacity / 2) & !1); // keeps the left/right pairs aligned
+        }
+        self.samples.push(left  / 4.0 * left_volume  / 8.0);
+        self.samples.push(right / 4.0 * right_volume / 8.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Apu,
+        CPU_CLOCK,
+    };
+
+    #[test]
+    fn sample_rate() {
+        let mut apu = Apu::new(32768);
+
+        apu.tick(CPU_CLOCK / 64);
+        assert_eq!(apu.take_samples().len(), 2 * 32768 / 64);
+        assert!(apu.take_samples().is_empty());
+
+        // an undrained buffer stops growing at a second of audio
+        apu.tick(3 * CPU_CLOCK);
+        let samples = apu.take_samples();
+        assert!(samples.len() <= 2 * 32768);
+        assert!(samples.len() >= 32768);
+        assert_eq!(samples.len() % 2, 0);
+    }
+    #[test]
+    fn square_output() {
+        let mut apu = Apu::new(32768);
+
+        apu.write(0xFF24, 0x77); // max volume
+        apu.write(0xFF25, 0x11); // channel 1 on both sides
+        apu.write(0xFF11, 0x80); // 50% duty
+        apu.write(0xFF12, 0xF0); // max volume, no envelope
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x87); // trigger
+        assert_eq!(apu.read(0xFF26), 0xF1);
+        assert_eq!(apu.read(0xFF11), 0xBF);
+
+        apu.tick(CPU_CLOCK / 64);
+        let samples = apu.take_samples();
+        assert!(samples.iter().any(|&sample| sample > 0.0));
+        assert!(samples.contains(&0.0));
+        assert_eq!(samples[0], samples[1]);
+    }
+    #[test]
+    fn length_counter() {
+        let mut apu = Apu::new(32768);
+
+        apu.write(0xFF21, 0xF0);
+        apu.write(0xFF20, 0x3F); // length of 1
+        apu.write(0xFF23, 0xC0); // trigger with length enabled
+        assert_eq!(apu.read(0xFF26) & 0x08, 0x08);
+
+        apu.tick(CPU_CLOCK / 256);
+        assert_eq!(apu.read(0xFF26) & 0x08, 0x00);
+    }
+    #[test]
+    fn unused_register() {
+        let mut apu = Apu::new(32768);
+
+        apu.write(0xFF21, 0xF0);
+        apu.write(0xFF1F, 0x80); // not NR44, no trigger
+        assert_eq!(apu.read(0xFF26) & 0x08, 0x00);
+    }
+    #[test]
+    fn power_off() {
+        let mut apu = Apu::new(32768);
+
+        apu.write(0xFF30, 0x12);
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF26, 0x00);
+        assert_eq!(apu.read(0xFF24), 0x00);
+        assert_eq!(apu.read(0xFF26), 0x70);
+        assert_eq!(apu.read(0xFF30), 0x12);
+
+        apu.write(0xFF24, 0x77); // ignored
+        assert_eq!(apu.read(0xFF24), 0x00);
+    }
+}
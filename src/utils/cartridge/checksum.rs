@@ -0,0 +1,101 @@
+use super::error::CartError;
+
+// Header fields checked by the boot ROM, and the global checksum that nothing checks
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#014d--header-checksum
+
+// 0x0104 - 0x0133: compared byte for byte by the boot ROM
+pub const NINTENDO_LOGO : [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+fn check_size(rom : &[u8]) -> Result<(), CartError> {
+    match rom.len() < 0x150 {
+        true  => Err(CartError::TooSmall(rom.len())),
+        false => Ok(()),
+    }
+}
+
+// 0x014D: complement sum of 0x0134 - 0x014C, the boot ROM locks up if it doesn't match
+pub fn header_checksum(rom : &[u8]) -> Result<u8, CartError> {
+    check_size(rom)?;
+    Ok(rom[0x134..=0x14C].iter().fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1)))
+}
+
+// 0x014E - 0x014F: big endian sum of every byte but the checksum itself
+pub fn global_checksum(rom : &[u8]) -> Result<u16, CartError> {
+    check_size(rom)?;
+    Ok(rom.iter().enumerate()
+        .filter(|(address, _)| !(0x14E..=0x14F).contains(address))
+        .fold(0u16, |sum, (_, byte)| sum.wrapping_add(*byte as u16)))
+}
+
+pub fn verify_header_checksum(rom : &[u8]) -> Result<(), CartError> {
+    let computed = header_checksum(rom)?;
+    let expected = rom[0x14D];
+    match expected == computed {
+        true  => Ok(()),
+        false => Err(CartError::HeaderChecksumMismatch { expected, computed }),
+    }
+}
+
+pub fn verify_global_checksum(rom : &[u8]) -> Result<(), CartError> {
+    let computed = global_checksum(rom)?;
+    let expected = u16::from_be_bytes([rom[0x14E], rom[0x14F]]);
+    match expected == computed {
+        true  => Ok(()),
+        false => Err(CartError::GlobalChecksumMismatch { expected, computed }),
+    }
+}
+
+// rewrites the logo and both checksums, the global one last as it covers the others
+pub fn fix_header(rom : &mut [u8]) -> Result<(), CartError> {
+    check_size(rom)?;
+    rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x14D] = header_checksum(rom)?;
+    let global = global_checksum(rom)?;
+    rom[0x14E..=0x14F].copy_from_slice(&global.to_be_bytes());
+    Ok(())
+}
+
+// fixes a ROM file in place, like rgbfix -v
+pub fn fix_header_file(path : &str) -> Result<(), CartError> {
+    let mut rom = std::fs::read(path)?;
+    fix_header(&mut rom)?;
+    std::fs::write(path, &rom)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        fix_header,
+        header_checksum,
+        global_checksum,
+        verify_header_checksum,
+        verify_global_checksum,
+        NINTENDO_LOGO,
+        CartError,
+    };
+
+    #[test]
+    fn fix() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        rom[0x1000] = 0xAB;
+
+        assert!(matches!(verify_header_checksum(&rom), Err(CartError::HeaderChecksumMismatch { expected : 0, .. })));
+        assert!(verify_global_checksum(&rom).is_err());
+
+        fix_header(&mut rom).unwrap();
+        assert_eq!(rom[0x104..0x134], NINTENDO_LOGO);
+        assert!(verify_header_checksum(&rom).is_ok());
+        assert!(verify_global_checksum(&rom).is_ok());
+
+        assert!(matches!(fix_header(&mut rom[..0x14F]), Err(CartError::TooSmall(0x14F))));
+        assert!(matches!(header_checksum(&rom[..0x100]), Err(CartError::TooSmall(0x100))));
+        assert!(matches!(global_checksum(&[]), Err(CartError::TooSmall(0))));
+        assert!(verify_header_checksum(&rom[..0x14D]).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+use super::header::CartridgeType;
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum CartError {
+    Io(std::io::Error),
+    TooSmall(usize), // the image ends before the header does
+    HeaderChecksumMismatch { expected : u8, computed : u8 },
+    GlobalChecksumMismatch { expected : u16, computed : u16 },
+    UnsupportedMapper(CartridgeType),
+    SizeMismatch { header : usize, actual : usize }, // ROM size code vs file size
+    InvalidState { expected : usize, actual : usize }, // save state or RAM image of the wrong size
+}
+
+impl fmt::Display for CartError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartError::Io(err) => write!(f, "{}", err),
+            CartError::TooSmall(size) => write!(f, "ROM is too small ({} bytes), the header ends at 0x150", size),
+            CartError::HeaderChecksumMismatch { expected, computed } =>
+                write!(f, "header checksum mismatch (header {:#04X}, computed {:#04X})", expected, computed),
+            CartError::GlobalChecksumMismatch { expected, computed } =>
+                write!(f, "global checksum mismatch (header {:#06X}, computed {:#06X})", expected, computed),
+            CartError::UnsupportedMapper(cart_type) =>
+                write!(f, "unsupported cartridge type {:#04X} ({})", cart_type.code(), cart_type.name()),
+            CartError::SizeMismatch { header, actual } =>
+                write!(f, "ROM size mismatch (header {} bytes, file {} bytes)", header, actual),
+            CartError::InvalidState { expected, actual } =>
+                write!(f, "invalid save state ({} bytes, expected {} bytes)", actual, expected),
+        }
+    }
+}
+
+impl std::error::Error for CartError {}
+
+impl From<std::io::Error> for CartError {
+    fn from(err : std::io::Error) -> Self {
+        CartError::Io(err)
+    }
+}
+
+// how the header is checked when a cartridge is loaded
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Validation {
+    #[default]
+    Strict,  // any inconsistency is an error
+    Lenient, // only unreadable images are errors, the rest is reported and ignored
+}
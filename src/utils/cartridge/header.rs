@@ -1,19 +1,350 @@
 use std::string::String;
 
+pub use super::licensee::Licensee;
+use super::{
+    checksum::NINTENDO_LOGO,
+    error::CartError,
+};
+
+// 0x0147: hardware on the cartridge
+// https://gbdev.io/pandocs/The_Cartridge_Header.html#0147--cartridge-type
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum CartridgeType {
+    #[default]
+    RomOnly,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    RomRam,
+    RomRamBattery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+    Unknown(u8),
+}
+
+use CartridgeType::*;
+
+const CARTRIDGE_TYPES : [(u8, CartridgeType, &str); 28] = [
+    (0x00, RomOnly,                    "ROM ONLY"),
+    (0x01, Mbc1,                       "MBC1"),
+    (0x02, Mbc1Ram,                    "MBC1+RAM"),
+    (0x03, Mbc1RamBattery,             "MBC1+RAM+BATTERY"),
+    (0x05, Mbc2,                       "MBC2"),
+    (0x06, Mbc2Battery,                "MBC2+BATTERY"),
+    (0x08, RomRam,                     "ROM+RAM"),
+    (0x09, RomRamBattery,              "ROM+RAM+BATTERY"),
+    (0x0B, Mmm01,                      "MMM01"),
+    (0x0C, Mmm01Ram,                   "MMM01+RAM"),
+    (0x0D, Mmm01RamBattery,            "MMM01+RAM+BATTERY"),
+    (0x0F, Mbc3TimerBattery,           "MBC3+TIMER+BATTERY"),
+    (0x10, Mbc3TimerRamBattery,        "MBC3+TIMER+RAM+BATTERY"),
+    (0x11, Mbc3,                       "MBC3"),
+    (0x12, Mbc3Ram,                    "MBC3+RAM"),
+    (0x13, Mbc3RamBattery,             "MBC3+RAM+BATTERY"),
+    (0x19, Mbc5,                       "MBC5"),
+    (0x1A, Mbc5Ram,                    "MBC5+RAM"),
+    (0x1B, Mbc5RamBattery,             "MBC5+RAM+BATTERY"),
+    (0x1C, Mbc5Rumble,                 "MBC5+RUMBLE"),
+    (0x1D, Mbc5RumbleRam,              "MBC5+RUMBLE+RAM"),
+    (0x1E, Mbc5RumbleRamBattery,       "MBC5+RUMBLE+RAM+BATTERY"),
+    (0x20, Mbc6,                       "MBC6"),
+    (0x22, Mbc7SensorRumbleRamBattery, "MBC7+SENSOR+RUMBLE+RAM+BATTERY"),
+    (0xFC, PocketCamera,               "POCKET CAMERA"),
+    (0xFD, BandaiTama5,                "BANDAI TAMA5"),
+    (0xFE, HuC3,                       "HuC3"),
+    (0xFF, HuC1RamBattery,             "HuC1+RAM+BATTERY"),
+];
+
+impl From<u8> for CartridgeType {
+    fn from(code : u8) -> Self {
+        CARTRIDGE_TYPES.iter()
+            .find(|(byte, _, _)| *byte == code)
+            .map_or(Unknown(code), |(_, cart_type, _)| *cart_type)
+    }
+}
+
+impl CartridgeType {
+    pub fn code(self) -> u8 {
+        match self {
+            Unknown(code) => code,
+            known => CARTRIDGE_TYPES.iter().find(|(_, cart_type, _)| *cart_type == known).unwrap().0,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        CARTRIDGE_TYPES.iter()
+            .find(|(_, cart_type, _)| *cart_type == self)
+            .map_or("Unknown", |(_, _, name)| name)
+    }
+    // external RAM on the cartridge, including the RAM built into MBC2
+    pub fn has_ram(self) -> bool {
+        matches!(self,
+            Mbc1Ram | Mbc1RamBattery | Mbc2 | Mbc2Battery | RomRam | RomRamBattery
+            | Mmm01Ram | Mmm01RamBattery | Mbc3TimerRamBattery | Mbc3Ram | Mbc3RamBattery
+            | Mbc5Ram | Mbc5RamBattery | Mbc5RumbleRam | Mbc5RumbleRamBattery
+            | Mbc7SensorRumbleRamBattery | PocketCamera | HuC3 | HuC1RamBattery)
+    }
+    pub fn has_battery(self) -> bool {
+        matches!(self,
+            Mbc1RamBattery | Mbc2Battery | RomRamBattery | Mmm01RamBattery
+            | Mbc3TimerBattery | Mbc3TimerRamBattery | Mbc3RamBattery
+            | Mbc5RamBattery | Mbc5RumbleRamBattery | Mbc7SensorRumbleRamBattery
+            | HuC3 | HuC1RamBattery)
+    }
+    pub fn has_rtc(self) -> bool {
+        matches!(self, Mbc3TimerBattery | Mbc3TimerRamBattery | HuC3)
+    }
+    pub fn has_rumble(self) -> bool {
+        matches!(self, Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery | Mbc7SensorRumbleRamBattery)
+    }
+}
+
+// 0x0148: ROM size, 32 KiB << code
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum RomSize {
+    #[default]
+    Kib32,
+    Kib64,
+    Kib128,
+    Kib256,
+    Kib512,
+    Mib1,
+    Mib2,
+    Mib4,
+    Mib8,
+    Mib1_1, // 72 banks
+    Mib1_2, // 80 banks
+    Mib1_5, // 96 banks
+    Unknown(u8),
+}
+
+impl From<u8> for RomSize {
+    fn from(code : u8) -> Self {
+        match code {
+            0x00 => RomSize::Kib32,
+            0x01 => RomSize::Kib64,
+            0x02 => RomSize::Kib128,
+            0x03 => RomSize::Kib256,
+            0x04 => RomSize::Kib512,
+            0x05 => RomSize::Mib1,
+            0x06 => RomSize::Mib2,
+            0x07 => RomSize::Mib4,
+            0x08 => RomSize::Mib8,
+            0x52 => RomSize::Mib1_1,
+            0x53 => RomSize::Mib1_2,
+            0x54 => RomSize::Mib1_5,
+            code => RomSize::Unknown(code),
+        }
+    }
+}
+
+impl RomSize {
+    pub fn code(self) -> u8 {
+        match self {
+            RomSize::Kib32   => 0x00,
+            RomSize::Kib64   => 0x01,
+            RomSize::Kib128  => 0x02,
+            RomSize::Kib256  => 0x03,
+            RomSize::Kib512  => 0x04,
+            RomSize::Mib1    => 0x05,
+            RomSize::Mib2    => 0x06,
+            RomSize::Mib4    => 0x07,
+            RomSize::Mib8    => 0x08,
+            RomSize::Mib1_1  => 0x52,
+            RomSize::Mib1_2  => 0x53,
+            RomSize::Mib1_5  => 0x54,
+            RomSize::Unknown(code) => code,
+        }
+    }
+    // number of 16 KiB banks, 0 for unknown sizes
+    pub fn banks(self) -> usize {
+        match self {
+            RomSize::Mib1_1 => 72,
+            RomSize::Mib1_2 => 80,
+            RomSize::Mib1_5 => 96,
+            RomSize::Unknown(_) => 0,
+            size => 2 << size.code(),
+        }
+    }
+    pub fn bytes(self) -> usize {
+        self.banks() * 0x4000
+    }
+}
+
+// 0x0149: external RAM size
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum RamSize {
+    #[default]
+    None,
+    Unused, // 2 KiB, listed in unofficial docs only
+    Kib8,
+    Kib32,
+    Kib128,
+    Kib64,
+    Unknown(u8),
+}
+
+impl From<u8> for RamSize {
+    fn from(code : u8) -> Self {
+        match code {
+            0x00 => RamSize::None,
+            0x01 => RamSize::Unused,
+            0x02 => RamSize::Kib8,
+            0x03 => RamSize::Kib32,
+            0x04 => RamSize::Kib128,
+            0x05 => RamSize::Kib64,
+            code => RamSize::Unknown(code),
+        }
+    }
+}
+
+impl RamSize {
+    pub fn code(self) -> u8 {
+        match self {
+            RamSize::None    => 0x00,
+            RamSize::Unused  => 0x01,
+            RamSize::Kib8    => 0x02,
+            RamSize::Kib32   => 0x03,
+            RamSize::Kib128  => 0x04,
+            RamSize::Kib64   => 0x05,
+            RamSize::Unknown(code) => code,
+        }
+    }
+    pub fn bytes(self) -> usize {
+        match self {
+            RamSize::Unused => 0x800,
+            RamSize::Kib8   => 0x2000,
+            RamSize::Kib32  => 0x8000,
+            RamSize::Kib128 => 0x20000,
+            RamSize::Kib64  => 0x10000,
+            _               => 0,
+        }
+    }
+    // number of 8 KiB banks, a partial bank counts as one
+    pub fn banks(self) -> usize {
+        self.bytes().div_ceil(0x2000)
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            RamSize::None    => "No RAM",
+            RamSize::Unused  => "Unused",
+            RamSize::Kib8    => "1 bank",
+            RamSize::Kib32   => "4 banks of 8 KiB each",
+            RamSize::Kib128  => "16 banks of 8 KiB each",
+            RamSize::Kib64   => "8 banks of 8 KiB each",
+            RamSize::Unknown(_) => "Unknown",
+        }
+    }
+}
+
+// 0x014A: where the game is sold
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Destination {
+    #[default]
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+impl From<u8> for Destination {
+    fn from(code : u8) -> Self {
+        match code {
+            0x00 => Destination::Japan,
+            0x01 => Destination::Overseas,
+            code => Destination::Unknown(code),
+        }
+    }
+}
+
+impl Destination {
+    pub fn code(self) -> u8 {
+        match self {
+            Destination::Japan    => 0x00,
+            Destination::Overseas => 0x01,
+            Destination::Unknown(code) => code,
+        }
+    }
+}
+
+// 0x0143: Game Boy Color support, shares its byte with the end of the title on older games
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum CgbFlag {
+    #[default]
+    Dmg,      // no CGB features
+    Enhanced, // 0x80: works on both, with CGB features
+    Only,     // 0xC0: CGB only
+}
+
+impl From<u8> for CgbFlag {
+    fn from(code : u8) -> Self {
+        match code {
+            0x80 => CgbFlag::Enhanced,
+            0xC0 => CgbFlag::Only,
+            _    => CgbFlag::Dmg,
+        }
+    }
+}
+
+// 0x0146: Super Game Boy functions, only honoured along with the old licensee code 0x33
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum SgbFlag {
+    #[default]
+    None,
+    Supported, // 0x03
+}
+
+impl From<u8> for SgbFlag {
+    fn from(code : u8) -> Self {
+        match code {
+            0x03 => SgbFlag::Supported,
+            _    => SgbFlag::None,
+        }
+    }
+}
+
+// manufacturer codes are 4 uppercase characters, older titles may run into that space
+fn is_manufacturer_code(bytes : &[u8]) -> bool {
+    bytes.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit())
+}
+
+fn text(bytes : &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string()
+}
+
 #[derive(Debug, Default)]
 pub struct RomHeader {
 
     pub entry : Vec<u8>,
     pub logo  : Vec<u8>,
     pub title : String,
+    pub manufacturer : String, // empty on cartridges without one
 
-    pub new_lic_code : String,
-    pub sgb_flag : u8,
-    pub cart_type : u8,
-    pub rom_size : u8,
-    pub ram_size : u8,
-    pub dest_code : u8,
-    pub lic_code : u8,
+    pub cgb_flag : CgbFlag,
+    pub sgb_flag : SgbFlag,
+    pub cart_type : CartridgeType,
+    pub rom_size : RomSize,
+    pub ram_size : RamSize,
+    pub destination : Destination,
+    pub licensee : Licensee,
     pub version : u8,
     pub checksum : u8,
     pub global_checksum : u16
@@ -23,32 +354,138 @@ impl RomHeader {
     pub fn new() -> Self {
         RomHeader::default()
     }
-    pub fn load(&mut self, header : &[u8]) -> Result<(), ()> {
+    // parses 0x0100 - 0x014F
+    pub fn load(&mut self, header : &[u8]) -> Result<(), CartError> {
+
+        if header.len() < 0x50 {
+            return Err(CartError::TooSmall(0x100 + header.len()));
+        }
 
-        self.entry = (&header[0x00..=0x03]).to_vec();
-        self.logo = (&header[0x04..=0x33]).to_vec();
+        self.entry = header[0x00..=0x03].to_vec();
+        self.logo = header[0x04..=0x33].to_vec();
 
-        self.title = String::from_utf8_lossy(&header[0x034..0x43]).into_owned();
+        // 0x0134 - 0x0143 is all title on DMG cartridges, colour cartridges use 0x0143
+        // as the CGB flag and may keep a manufacturer code in 0x013F - 0x0142
+        self.cgb_flag = CgbFlag::from(header[0x43]);
 
-        self.new_lic_code = String::from_utf8_lossy(&header[0x044..=0x45]).into_owned();
+        (self.title, self.manufacturer) = match self.cgb_flag {
+            CgbFlag::Dmg => (text(&header[0x34..=0x43]), String::new()),
+            _ if is_manufacturer_code(&header[0x3F..=0x42]) => (text(&header[0x34..=0x3E]), text(&header[0x3F..=0x42])),
+            _ => (text(&header[0x34..=0x42]), String::new()),
+        };
 
-        self.sgb_flag = header[0x46];
+        self.sgb_flag = SgbFlag::from(header[0x46]);
 
-        self.cart_type = header[0x47];
+        self.cart_type = CartridgeType::from(header[0x47]);
 
-        self.rom_size = header[0x48];
-        self.ram_size = header[0x49];
+        self.rom_size = RomSize::from(header[0x48]);
+        self.ram_size = RamSize::from(header[0x49]);
 
-        self.dest_code = header[0x4A];
+        self.destination = Destination::from(header[0x4A]);
 
-        self.lic_code = header[0x4B];
+        self.licensee = Licensee::from_codes(header[0x4B], &header[0x44..=0x45]);
 
         self.version = header[0x4C];
 
         self.checksum = header[0x4D];
 
-        self.global_checksum = (header[0x4F] as u16) << 8 | header[0x4E] as u16;
+        self.global_checksum = (header[0x4E] as u16) << 8 | header[0x4F] as u16; // big endian
 
         Ok(())
     }
+    // full logo check done by the DMG boot ROM
+    pub fn logo_valid(&self) -> bool {
+        self.logo == NINTENDO_LOGO
+    }
+    // the CGB boot ROM only compares the top half of the logo
+    pub fn logo_top_half_valid(&self) -> bool {
+        self.logo.len() == NINTENDO_LOGO.len() && self.logo[..0x18] == NINTENDO_LOGO[..0x18]
+    }
+    // SGB functions need both the flag and the old licensee code 0x33
+    pub fn sgb_supported(&self) -> bool {
+        self.sgb_flag == SgbFlag::Supported && self.licensee.code() == 0x33
+    }
+    // publisher name, from the old or the new licensee code
+    pub fn publisher(&self) -> &'static str {
+        self.licensee.name()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        CartridgeType,
+        RomSize,
+        RamSize,
+        Licensee,
+        CgbFlag,
+        RomHeader,
+        NINTENDO_LOGO,
+    };
+
+    #[test]
+    fn cartridge_type() {
+        for code in 0x00..=0xFF {
+            assert_eq!(CartridgeType::from(code).code(), code);
+        }
+        let mbc3 = CartridgeType::from(0x10);
+        assert_eq!(mbc3, CartridgeType::Mbc3TimerRamBattery);
+        assert_eq!(mbc3.name(), "MBC3+TIMER+RAM+BATTERY");
+        assert!(mbc3.has_ram() && mbc3.has_battery() && mbc3.has_rtc() && !mbc3.has_rumble());
+        assert!(CartridgeType::from(0x1C).has_rumble());
+        assert_eq!(CartridgeType::from(0x04).name(), "Unknown");
+    }
+    #[test]
+    fn sizes() {
+        assert_eq!(RomSize::from(0x00).bytes(), 0x8000);
+        assert_eq!(RomSize::from(0x08).banks(), 512);
+        assert_eq!(RomSize::from(0x53).banks(), 80);
+        assert_eq!(RamSize::from(0x03).banks(), 4);
+        assert_eq!(RamSize::from(0x01).banks(), 1);
+        assert_eq!(RamSize::from(0x05).bytes(), 0x10000);
+    }
+    #[test]
+    fn load() {
+        let mut bytes = [0u8; 0x50];
+        bytes[0x44..=0x45].copy_from_slice(b"01");
+        bytes[0x47] = 0x13;
+        bytes[0x4B] = 0x33;
+
+        let mut header = RomHeader::new();
+        assert!(header.load(&bytes[..0x4F]).is_err());
+        header.load(&bytes).unwrap();
+        assert_eq!(header.cart_type, CartridgeType::Mbc3RamBattery);
+        assert_eq!(header.licensee, Licensee::New(String::from("01")));
+        assert_eq!(header.licensee.name(), "Nintendo R&D1");
+    }
+    #[test]
+    fn title_and_flags() {
+        let mut bytes = [0u8; 0x50];
+        bytes[0x04..=0x33].copy_from_slice(&NINTENDO_LOGO);
+        bytes[0x34..=0x43].copy_from_slice(b"SUPER LONG TITLE");
+        bytes[0x46] = 0x03;
+        bytes[0x4B] = 0x33;
+
+        let mut header = RomHeader::new();
+        header.load(&bytes).unwrap();
+        assert_eq!(header.title, "SUPER LONG TITLE");
+        assert_eq!(header.cgb_flag, CgbFlag::Dmg);
+        assert!(header.logo_valid() && header.sgb_supported());
+
+        bytes[0x34..=0x43].copy_from_slice(b"POKEMON\0\0\0\0AAXE\x80");
+        bytes[0x30] = 0x00; // bottom half of the logo
+        header.load(&bytes).unwrap();
+        assert_eq!(header.title, "POKEMON");
+        assert_eq!(header.manufacturer, "AAXE");
+        assert_eq!(header.cgb_flag, CgbFlag::Enhanced);
+        assert!(!header.logo_valid() && header.logo_top_half_valid());
+
+        bytes[0x34..=0x43].copy_from_slice(b"ZELDA DX colour\xC0");
+        bytes[0x4B] = 0x01;
+        header.load(&bytes).unwrap();
+        assert_eq!(header.title, "ZELDA DX colour");
+        assert!(header.manufacturer.is_empty());
+        assert_eq!(header.cgb_flag, CgbFlag::Only);
+        assert!(!header.sgb_supported());
+    }
 }
@@ -0,0 +1,194 @@
+use super::{
+    error::CartError,
+    header::{RomHeader, CartridgeType},
+    mbc1::Mbc1,
+    mbc2::Mbc2,
+    mbc3::{Mbc3, RtcClock},
+    mbc5::Mbc5,
+};
+
+// Banking hardware on the cartridge, seen through the 0x0000 - 0x7FFF and 0xA000 - 0xBFFF windows.
+// The ROM image stays in `CartContext`, mappers only hold their registers and RAM.
+pub trait Mapper : std::fmt::Debug {
+    fn read_rom(&self, rom : &[u8], address : u16) -> u8;
+    // writes to the ROM area drive the mapper registers
+    fn write_rom(&mut self, address : u16, value : u8);
+    // external RAM, reads float high without it
+    fn read_ram(&self, _address : u16) -> u8 { 0xFF }
+    fn write_ram(&mut self, _address : u16, _value : u8) {}
+
+    // battery backed RAM to persist between sessions, None without a battery
+    fn save_ram(&self) -> Option<Vec<u8>> { None }
+    fn load_ram(&mut self, _data : &[u8]) {}
+
+    // registers and RAM, for save states
+    fn save_state(&self) -> Vec<u8>;
+    fn load_state(&mut self, state : &[u8]) -> Result<(), CartError>;
+
+    // hardware running on its own clock, like the MBC3 RTC
+    fn tick(&mut self, _cycles : u32) {}
+    fn set_rtc_clock(&mut self, _clock : RtcClock) {}
+    fn rumble(&self) -> bool { false }
+}
+
+pub const ROM_BANK_SIZE : usize = 0x4000;
+pub const RAM_BANK_SIZE : usize = 0x2000;
+
+// reads a byte out of a banked ROM, banks past the end of the image are mirrored
+pub fn rom_byte(rom : &[u8], bank : usize, address : u16) -> u8 {
+    let banks = (rom.len() / ROM_BANK_SIZE).max(1);
+    let offset = (bank % banks) * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1));
+    rom.get(offset).copied().unwrap_or(0xFF)
+}
+
+// test ROM where every bank starts with its own bank number, little endian
+#[cfg(test)]
+pub fn numbered_rom(banks : usize) -> Vec<u8> {
+    let mut rom = vec![0u8; banks * ROM_BANK_SIZE];
+    for bank in 0..banks {
+        rom[bank * ROM_BANK_SIZE]     = bank as u8;
+        rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+    }
+    rom
+}
+
+// save states are only accepted from a mapper with the same registers and RAM size
+pub fn check_state(state : &[u8], expected : usize) -> Result<(), CartError> {
+    match state.len() == expected {
+        true  => Ok(()),
+        false => Err(CartError::InvalidState { expected, actual : state.len() }),
+    }
+}
+
+// copies a saved RAM image over the mapper RAM, rejecting anything of the wrong size
+pub fn restore_ram(ram : &mut [u8], data : &[u8]) -> Result<(), CartError> {
+    check_state(data, ram.len())?;
+    ram.copy_from_slice(data);
+    Ok(())
+}
+
+// copies a battery save over the mapper RAM, saves from other emulators may be padded or truncated
+pub fn fill_ram(ram : &mut [u8], data : &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+// ROM only cartridges (0x00), optionally with up to 8 KiB of RAM (0x08 - 0x09)
+#[derive(Debug, Default)]
+pub struct RomOnly {
+    ram     : Vec<u8>,
+    battery : bool,
+}
+
+impl RomOnly {
+    pub fn new(ram_size : usize, battery : bool) -> Self {
+        RomOnly { ram : vec![0u8; ram_size], battery }
+    }
+}
+
+impl Mapper for RomOnly {
+    fn read_rom(&self, rom : &[u8], address : u16) -> u8 {
+        rom.get(address as usize).copied().unwrap_or(0xFF)
+    }
+    fn write_rom(&mut self, _address : u16, _value : u8) {}
+    fn read_ram(&self, address : u16) -> u8 {
+        self.ram.get(address as usize - 0xA000).copied().unwrap_or(0xFF)
+    }
+    fn write_ram(&mut self, address : u16, value : u8) {
+        if let Some(cell) = self.ram.get_mut(address as usize - 0xA000) {
+            *cell = value;
+        }
+    }
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.battery && !self.ram.is_empty() { Some(self.ram.clone()) } else { None }
+    }
+    fn load_ram(&mut self, data : &[u8]) {
+        fill_ram(&mut self.ram, data);
+    }
+    fn save_state(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+    fn load_state(&mut self, state : &[u8]) -> Result<(), CartError> {
+        restore_ram(&mut self.ram, state)
+    }
+}
+
+// picks the mapper wired to the cartridge type, None for hardware without an implementation
+pub fn from_header(header : &RomHeader, rom : &[u8]) -> Option<Box<dyn Mapper>> {
+    let ram = header.ram_size.bytes();
+    let mapper : Box<dyn Mapper> = match header.cart_type {
+        CartridgeType::RomOnly              => Box::new(RomOnly::new(0, false)),
+        CartridgeType::Mbc1                 => Box::new(Mbc1::new(0, false, Mbc1::is_multicart(rom))),
+        CartridgeType::Mbc1Ram              => Box::new(Mbc1::new(ram, false, Mbc1::is_multicart(rom))),
+        CartridgeType::Mbc1RamBattery       => Box::new(Mbc1::new(ram, true, Mbc1::is_multicart(rom))),
+        CartridgeType::Mbc2                 => Box::new(Mbc2::new(false)),
+        CartridgeType::Mbc2Battery          => Box::new(Mbc2::new(true)),
+        CartridgeType::RomRam               => Box::new(RomOnly::new(ram, false)),
+        CartridgeType::RomRamBattery        => Box::new(RomOnly::new(ram, true)),
+        CartridgeType::Mbc3TimerBattery     => Box::new(Mbc3::new(0, true, true)),
+        CartridgeType::Mbc3TimerRamBattery  => Box::new(Mbc3::new(ram, true, true)),
+        CartridgeType::Mbc3                 => Box::new(Mbc3::new(0, false, false)),
+        CartridgeType::Mbc3Ram              => Box::new(Mbc3::new(ram, false, false)),
+        CartridgeType::Mbc3RamBattery       => Box::new(Mbc3::new(ram, true, false)),
+        CartridgeType::Mbc5                 => Box::new(Mbc5::new(0, false, false)),
+        CartridgeType::Mbc5Ram              => Box::new(Mbc5::new(ram, false, false)),
+        CartridgeType::Mbc5RamBattery       => Box::new(Mbc5::new(ram, true, false)),
+        CartridgeType::Mbc5Rumble           => Box::new(Mbc5::new(0, false, true)),
+        CartridgeType::Mbc5RumbleRam        => Box::new(Mbc5::new(ram, false, true)),
+        CartridgeType::Mbc5RumbleRamBattery => Box::new(Mbc5::new(ram, true, true)),
+        _                                   => return None,
+    };
+    Some(mapper)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        from_header,
+        Mapper,
+        RomOnly,
+        CartError,
+    };
+    use crate::cartridge::header::{
+        RomHeader,
+        CartridgeType,
+        RamSize,
+    };
+
+    #[test]
+    fn rom_only() {
+        let rom = vec![0x42u8; 0x8000];
+        let mut mapper = RomOnly::new(0x2000, true);
+
+        mapper.write_rom(0x0000, 0x00);
+        assert_eq!(mapper.read_rom(&rom, 0x7FFF), 0x42);
+        assert_eq!(mapper.read_rom(&rom[..0x4000], 0x7FFF), 0xFF);
+
+        mapper.write_ram(0xA123, 0x55);
+        assert_eq!(mapper.read_ram(0xA123), 0x55);
+        assert_eq!(mapper.save_ram().map(|ram| ram[0x123]), Some(0x55));
+        assert_eq!(RomOnly::new(0, false).read_ram(0xA000), 0xFF);
+    }
+    #[test]
+    fn save_state() {
+        let rom = vec![0u8; 0x10000];
+        let mut header = RomHeader::new();
+        header.cart_type = CartridgeType::Mbc5RamBattery;
+        header.ram_size  = RamSize::Kib32;
+        let mut mapper = from_header(&header, &rom).unwrap();
+
+        mapper.write_rom(0x0000, 0x0A);
+        mapper.write_rom(0x4000, 0x02);
+        mapper.write_ram(0xA000, 0x77);
+        let state = mapper.save_state();
+
+        let mut restored = from_header(&header, &rom).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.read_ram(0xA000), 0x77);
+        assert!(matches!(restored.load_state(&state[..4]), Err(CartError::InvalidState { actual : 4, .. })));
+        assert_eq!(restored.save_ram().map(|ram| ram.len()), Some(0x8000));
+
+        header.cart_type = CartridgeType::HuC3;
+        assert!(from_header(&header, &rom).is_none());
+    }
+}
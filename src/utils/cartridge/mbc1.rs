@@ -0,0 +1,183 @@
+// MBC1 memory bank controller (cartridge types 0x01 - 0x03)
+// 0x0000 - 0x1FFF: RAM enable (0x0A in the lower nibble enables it)
+// 0x2000 - 0x3FFF: ROM bank number, lower 5 bits (0 is mapped as 1)
+// 0x4000 - 0x5FFF: RAM bank number or upper 2 bits of the ROM bank number
+// 0x6000 - 0x7FFF: Banking mode select
+// https://gbdev.io/pandocs/MBC1.html
+
+use super::error::CartError;
+use super::mapper::{
+    Mapper,
+    rom_byte,
+    restore_ram,
+    check_state,
+    ROM_BANK_SIZE,
+    RAM_BANK_SIZE,
+    fill_ram,
+};
+
+#[derive(Debug, Default)]
+pub struct Mbc1 {
+    ram         : Vec<u8>,
+    ram_enabled : bool,
+    rom_bank    : u8,   // 5-bit bank register
+    upper_bank  : u8,   // 2-bit bank register
+    mode        : bool, // advanced banking mode
+    multicart   : bool, // MBC1M wiring, the upper bits start at bit 4
+    battery     : bool,
+}
+
+impl Mbc1 {
+    pub fn new(ram_size : usize, battery : bool, multicart : bool) -> Self {
+        Mbc1 {
+            ram         : vec![0u8; ram_size],
+            ram_enabled : false,
+            rom_bank    : 1,
+            upper_bank  : 0,
+            mode        : false,
+            multicart,
+            battery,
+        }
+    }
+
+    // multicart collections repeat the Nintendo logo at the start of every 256 KiB game
+    pub fn is_multicart(rom : &[u8]) -> bool {
+        rom.len() == 64 * ROM_BANK_SIZE
+            && rom[0x104..0x134] == rom[0x10 * ROM_BANK_SIZE + 0x104..0x10 * ROM_BANK_SIZE + 0x134]
+    }
+
+    fn upper_shift(&self) -> u8 { if self.multicart { 4 } else { 5 } }
+
+    // bank mapped to 0x0000 - 0x3FFF
+    fn low_bank(&self) -> usize {
+        match self.mode {
+            true  => (self.upper_bank << self.upper_shift()) as usize,
+            false => 0,
+        }
+    }
+    // bank mapped to 0x4000 - 0x7FFF
+    fn high_bank(&self) -> usize {
+        let lower = match self.multicart {
+            true  => self.rom_bank & 0x0F,
+            false => self.rom_bank,
+        };
+        ((self.upper_bank << self.upper_shift()) | lower) as usize
+    }
+    fn ram_offset(&self, address : u16) -> usize {
+        let bank = if self.mode { self.upper_bank as usize } else { 0 };
+        (bank * RAM_BANK_SIZE + (address as usize - 0xA000)) % self.ram.len()
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn read_rom(&self, rom : &[u8], address : u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => self.low_bank(),
+            _               => self.high_bank(),
+        };
+        rom_byte(rom, bank, address)
+    }
+    fn write_rom(&mut self, address : u16, value : u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // the zero check only sees the 5-bit register, so banks 0x20/0x40/0x60 map to 0x21/0x41/0x61
+                self.rom_bank = match value & 0x1F {
+                    0 => 1,
+                    bank => bank,
+                };
+            },
+            0x4000..=0x5FFF => self.upper_bank = value & 0x03,
+            _               => self.mode = value & 0x01 != 0,
+        }
+    }
+    fn read_ram(&self, address : u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(address)]
+    }
+    fn write_ram(&mut self, address : u16, value : u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(address);
+        self.ram[offset] = value;
+    }
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.battery && !self.ram.is_empty() { Some(self.ram.clone()) } else { None }
+    }
+    fn load_ram(&mut self, data : &[u8]) {
+        fill_ram(&mut self.ram, data);
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.ram_enabled as u8, self.rom_bank, self.upper_bank, self.mode as u8];
+        state.extend_from_slice(&self.ram);
+        state
+    }
+    fn load_state(&mut self, state : &[u8]) -> Result<(), CartError> {
+        check_state(state, 4 + self.ram.len())?;
+        self.ram_enabled = state[0] != 0;
+        self.rom_bank    = state[1];
+        self.upper_bank  = state[2];
+        self.mode        = state[3] != 0;
+        restore_ram(&mut self.ram, &state[4..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Mbc1,
+        Mapper,
+    };
+    use crate::cartridge::mapper::numbered_rom;
+
+    #[test]
+    fn rom_banking() {
+        let rom = numbered_rom(128);
+        let mut mbc = Mbc1::new(0, false, false);
+
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+        mbc.write_rom(0x2000, 0x00); // bank 0 is remapped to 1
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x21);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x00);
+
+        mbc.write_rom(0x6000, 0x01); // advanced mode remaps 0x0000 - 0x3FFF
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x20);
+    }
+    #[test]
+    fn ram_banking() {
+        let mut mbc = Mbc1::new(0x8000, true, false);
+
+        mbc.write_ram(0xA000, 0x12); // disabled
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x12);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xA000, 0x34);
+        assert_eq!(mbc.read_ram(0xA000), 0x34);
+
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x12);
+    }
+    #[test]
+    fn multicart() {
+        let rom = numbered_rom(64);
+        let mut mbc = Mbc1::new(0, false, true);
+
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x12); // only 4 bits are wired
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x12);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000), 0x10);
+    }
+}
@@ -0,0 +1,132 @@
+// MBC2 memory bank controller (cartridge types 0x05 - 0x06)
+// 0x0000 - 0x3FFF: bit 8 of the address clear: RAM enable (0x0A in the lower nibble enables it)
+//                  bit 8 of the address set:   ROM bank number, 4 bits (0 is mapped as 1)
+// 0xA000 - 0xA1FF: built-in 512 x 4-bit RAM, echoed up to 0xBFFF
+// https://gbdev.io/pandocs/MBC2.html
+
+use super::error::CartError;
+use super::mapper::{
+    Mapper,
+    rom_byte,
+    restore_ram,
+    check_state,
+};
+
+pub const RAM_SIZE : usize = 0x200;
+
+#[derive(Debug, Default)]
+pub struct Mbc2 {
+    ram         : Vec<u8>, // only the lower nibble of each byte is stored
+    ram_enabled : bool,
+    rom_bank    : u8,      // 4-bit bank register
+    battery     : bool,
+}
+
+impl Mbc2 {
+    pub fn new(battery : bool) -> Self {
+        Mbc2 {
+            ram         : vec![0u8; RAM_SIZE],
+            ram_enabled : false,
+            rom_bank    : 1,
+            battery,
+        }
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn read_rom(&self, rom : &[u8], address : u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _               => self.rom_bank as usize,
+        };
+        rom_byte(rom, bank, address)
+    }
+    fn write_rom(&mut self, address : u16, value : u8) {
+        match address {
+            0x0000..=0x3FFF if address & 0x100 == 0 => self.ram_enabled = value & 0x0F == 0x0A,
+            0x0000..=0x3FFF => {
+                self.rom_bank = match value & 0x0F {
+                    0 => 1,
+                    bank => bank,
+                };
+            },
+            _ => {},
+        }
+    }
+    fn read_ram(&self, address : u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        0xF0 | self.ram[address as usize & (RAM_SIZE - 1)] // upper nibble is undefined, reads as 1s
+    }
+    fn write_ram(&mut self, address : u16, value : u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        self.ram[address as usize & (RAM_SIZE - 1)] = value & 0x0F;
+    }
+    // one nibble per byte
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.battery { Some(self.ram.clone()) } else { None }
+    }
+    fn load_ram(&mut self, data : &[u8]) {
+        for (cell, value) in self.ram.iter_mut().zip(data) {
+            *cell = value & 0x0F;
+        }
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.ram_enabled as u8, self.rom_bank];
+        state.extend_from_slice(&self.ram);
+        state
+    }
+    fn load_state(&mut self, state : &[u8]) -> Result<(), CartError> {
+        check_state(state, 2 + RAM_SIZE)?;
+        self.ram_enabled = state[0] != 0;
+        self.rom_bank    = state[1];
+        restore_ram(&mut self.ram, &state[2..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Mbc2,
+        Mapper,
+    };
+    use crate::cartridge::mapper::numbered_rom;
+
+    #[test]
+    fn registers() {
+        let rom = numbered_rom(16);
+        let mut mbc = Mbc2::new(false);
+
+        mbc.write_rom(0x2000, 0x05); // bit 8 clear, RAM enable register
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_rom(0x2100, 0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 5);
+        mbc.write_rom(0x0100, 0x10); // bank 0 is remapped to 1
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x12);
+        assert_eq!(mbc.read_ram(0xA000), 0xF2);
+        mbc.write_rom(0x0100, 0x00); // only selects the ROM bank
+        assert_eq!(mbc.read_ram(0xA000), 0xF2);
+        assert!(mbc.save_ram().is_none());
+    }
+    #[test]
+    fn ram_echo() {
+        let mut mbc = Mbc2::new(true);
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_ram(0xA1FF, 0x0C);
+        assert_eq!(mbc.read_ram(0xA3FF), 0xFC);
+        assert_eq!(mbc.read_ram(0xBFFF), 0xFC);
+        mbc.write_ram(0xB000, 0x07);
+        assert_eq!(mbc.read_ram(0xA000), 0xF7);
+        assert_eq!(mbc.save_ram().map(|ram| ram.len()), Some(0x200));
+
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+}
@@ -0,0 +1,500 @@
+// MBC3 memory bank controller (cartridge types 0x0F - 0x13)
+// 0x0000 - 0x1FFF: RAM and RTC enable (0x0A in the lower nibble enables them)
+// 0x2000 - 0x3FFF: ROM bank number, 7 bits (0 is mapped as 1)
+// 0x4000 - 0x5FFF: RAM bank number (0x00 - 0x03) or RTC register select (0x08 - 0x0C)
+// 0x6000 - 0x7FFF: Latch clock data (write 0x00 then 0x01)
+// https://gbdev.io/pandocs/MBC3.html
+
+use crate::cpu::CPU_CLOCK;
+use super::error::CartError;
+use super::mapper::{
+    Mapper,
+    rom_byte,
+    restore_ram,
+    check_state,
+    RAM_BANK_SIZE,
+    fill_ram,
+};
+
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+
+// clock registers, latched registers and the sub-second cycle count
+const RTC_STATE_SIZE : usize = 14;
+
+// clock appended to battery saves, as written by BGB and VBA and read by SameBoy and mGBA:
+// 5 x u32 registers, 5 x u32 latched registers, u64 unix timestamp (32-bit in older files)
+const RTC_FOOTER_SIZE : usize = 48;
+
+// what drives the real-time clock forward
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum RtcClock {
+    #[default]
+    Cycles,    // emulated cpu cycles, deterministic
+    WallClock, // host time, keeps running while the emulator is paused
+}
+
+// 0x08: seconds (0-59)
+// 0x09: minutes (0-59)
+// 0x0A: hours   (0-23)
+// 0x0B: lower 8 bits of the day counter
+// 0x0C: bit 0: bit 8 of the day counter, bit 6: halt, bit 7: day counter carry
+#[derive(Debug)]
+pub struct Rtc {
+    seconds : u8,
+    minutes : u8,
+    hours   : u8,
+    days    : u16,
+    halt    : bool,
+    carry   : bool,
+
+    latched     : [u8; 5], // registers as seen by the cpu
+    latch_write : u8,      // last value written to the latch register
+
+    pub clock : RtcClock,
+    cycles    : u32,        // cycles into the current second
+    last_sync : SystemTime, // host time already accounted for
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Rtc::new()
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Rtc {
+            seconds     : 0,
+            minutes     : 0,
+            hours       : 0,
+            days        : 0,
+            halt        : false,
+            carry       : false,
+            latched     : [0u8; 5],
+            latch_write : 0xFF,
+            clock       : RtcClock::Cycles,
+            cycles      : 0,
+            last_sync   : SystemTime::now(),
+        }
+    }
+
+    // current (unlatched) register values
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            (self.days >> 8) as u8 & 0x01 | (self.halt as u8) << 6 | (self.carry as u8) << 7,
+        ]
+    }
+
+    // counters only roll over at their nominal limit, out of range values wrap at the register width
+    fn advance_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 { return; }
+        self.seconds = 0;
+
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 { return; }
+        self.minutes = 0;
+
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 { return; }
+        self.hours = 0;
+
+        self.days += 1;
+        if self.days == 512 {
+            self.days  = 0;
+            self.carry = true;
+        }
+    }
+    // skips ahead without stepping through every second, so months old saves catch up at once
+    pub fn advance(&mut self, mut seconds : u64) {
+        if self.halt { return; }
+
+        // out of range values first have to wrap the slow way, which takes at most a few hours
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.advance_second();
+            seconds -= 1;
+        }
+
+        let total = self.seconds as u64 + seconds;
+        self.seconds = (total % 60) as u8;
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % 60) as u8;
+        let total = self.hours as u64 + total / 60;
+        self.hours = (total % 24) as u8;
+        let days = self.days as u64 + total / 24;
+        if days >= 512 {
+            self.carry = true;
+        }
+        self.days = (days % 512) as u16;
+    }
+
+    pub fn tick(&mut self, cycles : u32) {
+        if self.clock != RtcClock::Cycles || self.halt {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CPU_CLOCK {
+            self.cycles -= CPU_CLOCK;
+            self.advance_second();
+        }
+    }
+    // catches up with the host time elapsed since the last sync
+    pub fn sync(&mut self) {
+        if self.clock != RtcClock::WallClock {
+            return;
+        }
+        let now = SystemTime::now();
+        if let Ok(elapsed) = now.duration_since(self.last_sync) {
+            let seconds = elapsed.as_secs();
+            self.advance(seconds);
+            self.last_sync += Duration::from_secs(seconds);
+        } else {
+            self.last_sync = now; // host clock went backwards
+        }
+    }
+
+    pub fn latch(&mut self, value : u8) {
+        if self.latch_write == 0x00 && value == 0x01 {
+            self.sync();
+            self.latched = self.registers();
+        }
+        self.latch_write = value;
+    }
+    pub fn save_state(&self) -> [u8; RTC_STATE_SIZE] {
+        let mut state = [0u8; RTC_STATE_SIZE];
+        state[0..5].copy_from_slice(&self.registers());
+        state[5..10].copy_from_slice(&self.latched);
+        state[10..14].copy_from_slice(&self.cycles.to_le_bytes());
+        state
+    }
+    pub fn load_state(&mut self, state : &[u8]) {
+        self.seconds = state[0];
+        self.minutes = state[1];
+        self.hours   = state[2];
+        self.days    = state[3] as u16 | (state[4] as u16 & 0x01) << 8;
+        self.halt    = state[4] & 0x40 != 0;
+        self.carry   = state[4] & 0x80 != 0;
+        self.latched.copy_from_slice(&state[5..10]);
+        self.cycles    = u32::from_le_bytes([state[10], state[11], state[12], state[13]]);
+        self.last_sync = SystemTime::now();
+    }
+    pub fn save_footer(&self) -> [u8; RTC_FOOTER_SIZE] {
+        let mut footer = [0u8; RTC_FOOTER_SIZE];
+        let registers = self.registers().into_iter().chain(self.latched);
+        for (chunk, value) in footer.chunks_exact_mut(4).zip(registers) {
+            chunk.copy_from_slice(&(value as u32).to_le_bytes());
+        }
+        // cycle driven registers are up to date right now, host time ones as of the last sync
+        let time = match self.clock {
+            RtcClock::Cycles    => SystemTime::now(),
+            RtcClock::WallClock => self.last_sync,
+        };
+        let timestamp = time.duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        footer[40..48].copy_from_slice(&timestamp.to_le_bytes());
+        footer
+    }
+    // the clock catches up from the save timestamp on the next sync when running on host time
+    pub fn load_footer(&mut self, footer : &[u8]) {
+        let word = |index : usize| footer[index * 4];
+        self.seconds = word(0) & 0x3F;
+        self.minutes = word(1) & 0x3F;
+        self.hours   = word(2) & 0x1F;
+        self.days    = word(3) as u16 | (word(4) as u16 & 0x01) << 8;
+        self.halt    = word(4) & 0x40 != 0;
+        self.carry   = word(4) & 0x80 != 0;
+        for (index, latched) in self.latched.iter_mut().enumerate() {
+            *latched = word(5 + index);
+        }
+        let mut timestamp = [0u8; 8];
+        let stored = &footer[40..];
+        timestamp[..stored.len()].copy_from_slice(stored);
+        self.last_sync = UNIX_EPOCH + Duration::from_secs(u64::from_le_bytes(timestamp));
+    }
+    pub fn read(&self, reg : u8) -> u8 {
+        match reg {
+            0x08 | 0x09 => self.latched[(reg - 0x08) as usize] & 0x3F,
+            0x0A        => self.latched[2] & 0x1F,
+            0x0B        => self.latched[3],
+            0x0C        => self.latched[4] & 0xC1,
+            _           => 0xFF,
+        }
+    }
+    pub fn write(&mut self, reg : u8, value : u8) {
+        self.sync();
+        match reg {
+            0x08 => {
+                self.seconds = value & 0x3F;
+                self.cycles  = 0; // writing the seconds resets the sub-second divider
+            },
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours   = value & 0x1F,
+            0x0B => self.days    = (self.days & 0x100) | value as u16,
+            0x0C => {
+                self.days  = (self.days & 0xFF) | (value as u16 & 0x01) << 8;
+                self.halt  = value & 0x40 != 0;
+                self.carry = value & 0x80 != 0;
+            },
+            _    => {},
+        }
+        // writes are visible right away
+        if (0x08..=0x0C).contains(&reg) {
+            self.latched[(reg - 0x08) as usize] = self.registers()[(reg - 0x08) as usize];
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Mbc3 {
+    ram      : Vec<u8>,
+    pub rtc  : Option<Rtc>,
+    enabled  : bool, // RAM and RTC access
+    rom_bank : u8,   // 7-bit bank register
+    select   : u8,   // RAM bank or RTC register
+    battery  : bool,
+}
+
+impl Mbc3 {
+    pub fn new(ram_size : usize, battery : bool, has_rtc : bool) -> Self {
+        Mbc3 {
+            ram      : vec![0u8; ram_size],
+            rtc      : if has_rtc { Some(Rtc::new()) } else { None },
+            enabled  : false,
+            rom_bank : 1,
+            select   : 0,
+            battery,
+        }
+    }
+    fn ram_offset(&self, address : u16) -> usize {
+        (self.select as usize * RAM_BANK_SIZE + (address as usize - 0xA000)) % self.ram.len()
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn read_rom(&self, rom : &[u8], address : u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _               => self.rom_bank as usize,
+        };
+        rom_byte(rom, bank, address)
+    }
+    fn write_rom(&mut self, address : u16, value : u8) {
+        match address {
+            0x0000..=0x1FFF => self.enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = match value & 0x7F {
+                    0 => 1,
+                    bank => bank,
+                };
+            },
+            0x4000..=0x5FFF => self.select = value & 0x0F,
+            _               => if let Some(rtc) = self.rtc.as_mut() { rtc.latch(value) },
+        }
+    }
+    fn read_ram(&self, address : u16) -> u8 {
+        if !self.enabled {
+            return 0xFF;
+        }
+        match (self.select, self.rtc.as_ref()) {
+            (0x00..=0x03, _) if !self.ram.is_empty() => self.ram[self.ram_offset(address)],
+            (0x08..=0x0C, Some(rtc))                 => rtc.read(self.select),
+            _                                        => 0xFF,
+        }
+    }
+    fn write_ram(&mut self, address : u16, value : u8) {
+        if !self.enabled {
+            return;
+        }
+        match self.select {
+            0x00..=0x03 if !self.ram.is_empty() => {
+                let offset = self.ram_offset(address);
+                self.ram[offset] = value;
+            },
+            0x08..=0x0C => if let Some(rtc) = self.rtc.as_mut() { rtc.write(self.select, value) },
+            _ => {},
+        }
+    }
+    // RAM followed by the clock footer on cartridges with a RTC
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if !self.battery || (self.ram.is_empty() && self.rtc.is_none()) {
+            return None;
+        }
+        let mut data = self.ram.clone();
+        if let Some(rtc) = self.rtc.as_ref() {
+            data.extend_from_slice(&rtc.save_footer());
+        }
+        Some(data)
+    }
+    fn load_ram(&mut self, data : &[u8]) {
+        let ram_len = self.ram.len().min(data.len());
+        fill_ram(&mut self.ram, &data[..ram_len]);
+
+        let footer = &data[ram_len..];
+        if let (Some(rtc), 44 | RTC_FOOTER_SIZE) = (self.rtc.as_mut(), footer.len()) {
+            rtc.load_footer(footer);
+        }
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.enabled as u8, self.rom_bank, self.select];
+        if let Some(rtc) = self.rtc.as_ref() {
+            state.extend_from_slice(&rtc.save_state());
+        }
+        state.extend_from_slice(&self.ram);
+        state
+    }
+    fn load_state(&mut self, state : &[u8]) -> Result<(), CartError> {
+        let rtc_len = if self.rtc.is_some() { RTC_STATE_SIZE } else { 0 };
+        check_state(state, 3 + rtc_len + self.ram.len())?;
+        self.enabled  = state[0] != 0;
+        self.rom_bank = state[1];
+        self.select   = state[2];
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.load_state(&state[3..3 + rtc_len]);
+        }
+        restore_ram(&mut self.ram, &state[3 + rtc_len..])
+    }
+    fn tick(&mut self, cycles : u32) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.tick(cycles);
+        }
+    }
+    fn set_rtc_clock(&mut self, clock : RtcClock) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.clock = clock;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Mbc3,
+        Mapper,
+        Rtc,
+        CPU_CLOCK,
+        UNIX_EPOCH,
+        SystemTime,
+        Duration,
+    };
+    use crate::cartridge::mapper::numbered_rom;
+
+    #[test]
+    fn banking() {
+        let rom = numbered_rom(128);
+        let mut mbc = Mbc3::new(0x8000, false, false);
+
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 1);
+        mbc.write_rom(0x2000, 0x7F);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x7F);
+
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_ram(0xA000, 0x33);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x00);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_ram(0xA000), 0x33);
+        mbc.write_rom(0x4000, 0x08); // no clock on this cartridge
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+    #[test]
+    fn rtc_latch() {
+        let mut mbc = Mbc3::new(0, false, true);
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.tick(CPU_CLOCK * 61);
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 0); // not latched yet
+
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(0xA000), 1);
+        mbc.write_rom(0x4000, 0x09);
+        assert_eq!(mbc.read_ram(0xA000), 1);
+
+        mbc.tick(CPU_CLOCK);
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 1); // holds the latched value
+    }
+    #[test]
+    fn battery_save() {
+        let mut mbc = Mbc3::new(0x2000, true, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_ram(0xA000, 0x99);
+        mbc.write_rom(0x4000, 0x0A);
+        mbc.write_ram(0xA000, 0x05); // 5 hours
+
+        let data = mbc.save_ram().unwrap();
+        assert_eq!(data.len(), 0x2000 + 48);
+        assert_eq!(data[0x2000 + 8], 0x05);
+
+        let mut restored = Mbc3::new(0x2000, true, true);
+        restored.load_ram(&data[..0x2000 + 44]); // 32-bit timestamp
+        restored.write_rom(0x0000, 0x0A);
+        restored.write_rom(0x6000, 0x00);
+        restored.write_rom(0x6000, 0x01);
+        restored.write_rom(0x4000, 0x0A);
+        assert_eq!(restored.read_ram(0xA000), 0x05);
+        restored.write_rom(0x4000, 0x00);
+        assert_eq!(restored.read_ram(0xA000), 0x99);
+    }
+    #[test]
+    fn rtc_rollover() {
+        let mut mbc = Mbc3::new(0, false, true);
+        mbc.write_rom(0x0000, 0x0A);
+
+        // 23:59:59 on day 511
+        for (reg, value) in [(0x08, 59), (0x09, 59), (0x0A, 23), (0x0B, 0xFF), (0x0C, 0x01)] {
+            mbc.write_rom(0x4000, reg);
+            mbc.write_ram(0xA000, value);
+        }
+        mbc.tick(CPU_CLOCK);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+
+        for (reg, value) in [(0x08, 0), (0x09, 0), (0x0A, 0), (0x0B, 0), (0x0C, 0x80)] {
+            mbc.write_rom(0x4000, reg);
+            assert_eq!(mbc.read_ram(0xA000), value);
+        }
+
+        // halted clocks don't advance
+        mbc.write_ram(0xA000, 0x40);
+        mbc.tick(CPU_CLOCK * 2);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        mbc.write_rom(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(0xA000), 0);
+    }
+    #[test]
+    fn rtc_catch_up() {
+        let mut rtc = Rtc::new();
+        rtc.advance(600 * 86400 + 3 * 3600 + 2 * 60 + 1); // 600 days, 03:02:01
+        assert_eq!(rtc.registers(), [1, 2, 3, (600 - 512) as u8, 0x80]);
+
+        // out of range values wrap at the register width before rolling over normally
+        let mut rtc = Rtc::new();
+        rtc.seconds = 62;
+        rtc.hours   = 30;
+        rtc.advance(2 + 3600 * 2 + 60);
+        assert_eq!(rtc.registers(), [0, 1, 0, 0, 0x00]);
+
+        rtc.halt = true;
+        rtc.advance(1_000_000);
+        assert_eq!(rtc.registers(), [0, 1, 0, 0, 0x40]);
+
+        // the footer of a cycle driven clock is stamped with the save time
+        let mut rtc = Rtc::new();
+        rtc.last_sync = UNIX_EPOCH;
+        let footer = rtc.save_footer();
+        let saved = UNIX_EPOCH + Duration::from_secs(u64::from_le_bytes(footer[40..48].try_into().unwrap()));
+        assert!(SystemTime::now().duration_since(saved).unwrap().as_secs() < 60);
+    }
+}
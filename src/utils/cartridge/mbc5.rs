@@ -0,0 +1,153 @@
+// MBC5 memory bank controller (cartridge types 0x19 - 0x1E)
+// 0x0000 - 0x1FFF: RAM enable (0x0A in the lower nibble enables it)
+// 0x2000 - 0x2FFF: lower 8 bits of the ROM bank number (0 is a valid bank)
+// 0x3000 - 0x3FFF: bit 8 of the ROM bank number
+// 0x4000 - 0x5FFF: RAM bank number (0x00 - 0x0F), bit 3 drives the motor on rumble cartridges
+// https://gbdev.io/pandocs/MBC5.html
+
+use super::error::CartError;
+use super::mapper::{
+    Mapper,
+    rom_byte,
+    restore_ram,
+    check_state,
+    RAM_BANK_SIZE,
+    fill_ram,
+};
+
+#[derive(Debug, Default)]
+pub struct Mbc5 {
+    ram         : Vec<u8>,
+    ram_enabled : bool,
+    rom_bank    : u16,  // 9-bit bank register
+    ram_bank    : u8,   // 4-bit bank register
+    has_rumble  : bool, // bit 3 of the RAM bank is wired to the motor
+    rumble      : bool, // motor state
+    battery     : bool,
+}
+
+impl Mbc5 {
+    pub fn new(ram_size : usize, battery : bool, has_rumble : bool) -> Self {
+        Mbc5 {
+            ram         : vec![0u8; ram_size],
+            ram_enabled : false,
+            rom_bank    : 1,
+            ram_bank    : 0,
+            has_rumble,
+            rumble      : false,
+            battery,
+        }
+    }
+    fn ram_offset(&self, address : u16) -> usize {
+        (self.ram_bank as usize * RAM_BANK_SIZE + (address as usize - 0xA000)) % self.ram.len()
+    }
+}
+
+impl Mapper for Mbc5 {
+    fn read_rom(&self, rom : &[u8], address : u16) -> u8 {
+        let bank = match address {
+            0x0000..=0x3FFF => 0,
+            _               => self.rom_bank as usize,
+        };
+        rom_byte(rom, bank, address)
+    }
+    fn write_rom(&mut self, address : u16, value : u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0x0FF) | (value as u16 & 0x01) << 8,
+            0x4000..=0x5FFF => {
+                match self.has_rumble {
+                    true  => {
+                        self.rumble   = value & 0x08 != 0;
+                        self.ram_bank = value & 0x07;
+                    },
+                    false => self.ram_bank = value & 0x0F,
+                }
+            },
+            _ => {},
+        }
+    }
+    fn read_ram(&self, address : u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[self.ram_offset(address)]
+    }
+    fn write_ram(&mut self, address : u16, value : u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let offset = self.ram_offset(address);
+        self.ram[offset] = value;
+    }
+    fn save_ram(&self) -> Option<Vec<u8>> {
+        if self.battery && !self.ram.is_empty() { Some(self.ram.clone()) } else { None }
+    }
+    fn load_ram(&mut self, data : &[u8]) {
+        fill_ram(&mut self.ram, data);
+    }
+    fn save_state(&self) -> Vec<u8> {
+        let [bank_low, bank_high] = self.rom_bank.to_le_bytes();
+        let mut state = vec![self.ram_enabled as u8, bank_low, bank_high, self.ram_bank, self.rumble as u8];
+        state.extend_from_slice(&self.ram);
+        state
+    }
+    fn load_state(&mut self, state : &[u8]) -> Result<(), CartError> {
+        check_state(state, 5 + self.ram.len())?;
+        self.ram_enabled = state[0] != 0;
+        self.rom_bank    = u16::from_le_bytes([state[1], state[2]]);
+        self.ram_bank    = state[3];
+        self.rumble      = state[4] != 0;
+        restore_ram(&mut self.ram, &state[5..])
+    }
+    fn rumble(&self) -> bool { self.rumble }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Mbc5,
+        Mapper,
+    };
+    use crate::cartridge::mapper::numbered_rom;
+
+    #[test]
+    fn rom_banking() {
+        let rom = numbered_rom(512);
+        let mut mbc = Mbc5::new(0, false, false);
+
+        mbc.write_rom(0x2000, 0x00); // bank 0 is not remapped
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0);
+        mbc.write_rom(0x2000, 0x34);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x4000), 0x34);
+        assert_eq!(mbc.read_rom(&rom, 0x4001), 0x01);
+    }
+    #[test]
+    fn ram_banking() {
+        let mut mbc = Mbc5::new(0x20000, false, false);
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_rom(0x4000, 0x0F);
+        mbc.write_ram(0xBFFF, 0x5A);
+        mbc.write_rom(0x4000, 0x00);
+        assert_eq!(mbc.read_ram(0xBFFF), 0x00);
+        mbc.write_rom(0x4000, 0x0F);
+        assert_eq!(mbc.read_ram(0xBFFF), 0x5A);
+        assert!(!mbc.rumble());
+    }
+    #[test]
+    fn rumble() {
+        let mut mbc = Mbc5::new(0x8000, false, true);
+        mbc.write_rom(0x0000, 0x0A);
+
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0xA000, 0x11);
+        mbc.write_rom(0x4000, 0x09); // motor on, same bank
+        assert!(mbc.rumble());
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+        mbc.write_rom(0x4000, 0x01);
+        assert!(!mbc.rumble());
+    }
+}
@@ -1,14 +1,31 @@
+pub mod checksum;
+pub mod error;
 pub mod header;
-use super::cartridge::header::RomHeader;
+pub mod licensee;
+pub mod mapper;
+pub mod mbc1;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+use super::cartridge::{
+    error::{CartError, Validation},
+    header::RomHeader,
+    mapper::{Mapper, RomOnly},
+    mbc3::RtcClock,
+};
 
 use std::io::Read;
+use std::path::{
+    Path,
+    PathBuf,
+};
 
 fn human_readable(size : usize) -> String {
 
     // auxiliar function to convert from byte size to human readable representation
 
     let mut sz = size as f32; 
-    let units = vec!["B","KiB","MiB","GiB","TiB"];
+    let units = ["B","KiB","MiB","GiB","TiB"];
     let mut idx = 0;
     
     let result = loop {
@@ -21,11 +38,23 @@ fn human_readable(size : usize) -> String {
     result
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct CartContext {
     pub header   : RomHeader,
     pub rom_size : usize,
     pub rom_data : Vec<u8>,
+    pub mapper   : Box<dyn Mapper>, // banking hardware selected from the cartridge type
+
+    pub save_path : Option<PathBuf>, // battery save, `<rom>.sav` next to the ROM
+    saved         : Option<Vec<u8>>, // RAM contents as last written to the save file
+
+    pub warnings : Vec<CartError>, // problems found while loading that didn't stop it
+}
+
+impl Default for CartContext {
+    fn default() -> Self {
+        CartContext::new()
+    }
 }
 
 impl CartContext {
@@ -34,162 +63,166 @@ impl CartContext {
             header   : RomHeader::new(),
             rom_data : vec![0u8; 0x8000],
             rom_size : 0x8000,
+            mapper   : Box::new(RomOnly::new(0, false)),
+            save_path : None,
+            saved     : None,
+            warnings  : Vec::new(),
         }
     }
-    pub fn load(&mut self, filename : &str) -> Result<(), ()> {
+    // in-memory image, mapped from address 0, with the default validation
+    pub fn from_bytes(data : Vec<u8>) -> Result<Self, CartError> {
+        CartContext::from_bytes_with_validation(data, Validation::default())
+    }
+    pub fn from_reader(reader : impl Read) -> Result<Self, CartError> {
+        CartContext::from_reader_with_validation(reader, Validation::default())
+    }
+    pub fn from_bytes_with_validation(data : Vec<u8>, validation : Validation) -> Result<Self, CartError> {
+        let mut cartridge = CartContext::new();
+        cartridge.parse(data, validation)?;
+        Ok(cartridge)
+    }
+    pub fn from_reader_with_validation(mut reader : impl Read, validation : Validation) -> Result<Self, CartError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        CartContext::from_bytes_with_validation(data, validation)
+    }
+    // loads a ROM file along with its battery save
+    pub fn load(&mut self, filename : &str, validation : Validation) -> Result<(), CartError> {
+
+        let data = std::fs::read(filename)?;
+        self.parse(data, validation)?;
 
-        let mut file = std::fs::File::open(filename).expect("Run open failed");
+        self.save_path = Some(Path::new(filename).with_extension("sav"));
+        if let Err(err) = self.load_save() {
+            self.warnings.push(err.into());
+        }
 
-        file.read_to_end(&mut self.rom_data).expect("Run read failed");
+        Ok(())
+    }
+    // checks the header and selects the mapper
+    fn parse(&mut self, data : Vec<u8>, validation : Validation) -> Result<(), CartError> {
 
+        self.rom_data = data;
         self.rom_size = self.rom_data.len();
+        if self.rom_size < 0x150 {
+            return Err(CartError::TooSmall(self.rom_size));
+        }
 
-        self.header.load(&self.rom_data[0x100..=0x14F]);
-
-        let cart_type = match self.header.cart_type {
-            0x00    => "ROM ONLY",
-            0x01    => "MBC1",
-            0x02    => "MBC1+RAM",
-            0x03    => "MBC1+RAM+BATTERY",
-            0x05    => "MBC2",
-            0x06    => "MBC2+BATTERY",
-            0x08    => "ROM+RAM",
-            0x09    => "ROM+RAM+BATTERY",
-            0x0B    => "MMM01",
-            0x0C    => "MMM01+RAM",
-            0x0D    => "MMM01+RAM+BATTERY",
-            0x0F    => "MBC3+TIMER+BATTERY",
-            0x10    => "MBC3+TIMER+RAM+BATTERY",
-            0x11    => "MBC3",
-            0x12    => "MBC3+RAM",
-            0x13    => "MBC3+RAM+BATTERY",
-            0x19    => "MBC5",
-            0x1A    => "MBC5+RAM",
-            0x1B    => "MBC5+RAM+BATTERY",
-            0x1C    => "MBC5+RUMBLE",
-            0x1D    => "MBC5+RUMBLE+RAM",
-            0x1E    => "MBC5+RUMBLE+RAM+BATTERY",
-            0x20    => "MBC6",
-            0x22    => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
-            0xFC    => "POCKET CAMERA",
-            0xFD    => "BANDAI TAMA5",
-            0xFE    => "HuC3",
-            0xFF    => "HuC1+RAM+BATTERY",
-            _       => "Unknown"
-        };
+        self.header.load(&self.rom_data[0x100..=0x14F])?;
 
-        let rom_size = human_readable(match self.header.rom_size {
-            0x00..=0x08 => (1 << (15 + self.header.rom_size)),
-            0x52        => ((1.1 * ((1 << 20) as f64)) as usize),
-            0x53        => ((1.2 * ((1 << 20) as f64)) as usize),
-            0x54        => ((1.5 * ((1 << 20) as f64)) as usize),
-            _           => 0
-        });
-
-        let ram_size = match self.header.ram_size {
-            0x00    => "No RAM",
-            0x01    => "Unused",
-            0x02    => "1 bank",
-            0x03    => "4 banks of 8 KiB each",
-            0x04    => "16 banks of 8 KiB each",
-            0x05    => "8 banks of 8 KiB each",
-            _       => "Unknown"
+        // strict loading stops at the first problem, lenient loading records it as a warning and carries on
+        let mut warnings = Vec::new();
+        let mut check = |result : Result<(), CartError>| match (result, validation) {
+            (Err(err), Validation::Lenient) => {
+                warnings.push(err);
+                Ok(())
+            },
+            (result, _) => result,
         };
 
-        let lic_name = match self.header.lic_code {
-            0x33 => {
-                match self.header.new_lic_code.as_str() {
-                    "00"    => "None",
-                    "01"    => "Nintendo R&D1",
-                    "08"    => "Capcom",
-                    "13"    => "Electronic Arts",
-                    "18"    => "Hudson Soft",
-                    "19"    => "b-ai",
-                    "20"    => "kss",
-                    "22"    => "pow",
-                    "24"    => "PCM Complete",
-                    "25"    => "san-x",
-                    "28"    => "Kemco Japan",
-                    "29"    => "seta",
-                    "30"    => "Viacom",
-                    "31"    => "Nintendo",
-                    "32"    => "Bandai",
-                    "33"    => "Ocean/Acclaim",
-                    "34"    => "Konami",
-                    "35"    => "Hector",
-                    "37"    => "Taito",
-                    "38"    => "Hudson",
-                    "39"    => "Banpresto",
-                    "41"    => "Ubi Soft",
-                    "42"    => "Atlus",
-                    "44"    => "Malibu",
-                    "46"    => "angel",
-                    "47"    => "Bullet-Proof",
-                    "49"    => "irem",
-                    "50"    => "Absolute",
-                    "51"    => "Acclaim",
-                    "52"    => "Activision",
-                    "53"    => "American sammy",
-                    "54"    => "Konami",
-                    "55"    => "Hi tech entertainment",
-                    "56"    => "LJN",
-                    "57"    => "Matchbox",
-                    "58"    => "Mattel",
-                    "59"    => "Milton Bradley",
-                    "60"    => "Titus",
-                    "61"    => "Virgin",
-                    "64"    => "LucasArts",
-                    "67"    => "Ocean",
-                    "69"    => "Electronic Arts",
-                    "70"    => "Infogrames",
-                    "71"    => "Interplay",
-                    "72"    => "Broderbund",
-                    "73"    => "sculptured",
-                    "75"    => "sci",
-                    "78"    => "THQ",
-                    "79"    => "Accolade",
-                    "80"    => "misawa",
-                    "83"    => "lozc",
-                    "86"    => "Tokuma Shoten Intermedia",
-                    "87"    => "Tsukuda Original",
-                    "91"    => "Chunsoft",
-                    "92"    => "Video system",
-                    "93"    => "Ocean/Acclaim",
-                    "95"    => "Varie",
-                    "96"    => "Yonezawa/s'pal",
-                    "97"    => "Kaneko",
-                    "99"    => "Pack in soft",
-                    "A4"    => "Konami (Yu-Gi-Oh!)",
-                    _       => "Unknown"
-                }
-            }
-            _ => "Unknown"
+        // If the byte at $014D does not match the lower 8 bits of checksum,
+        // the boot ROM will lock up and the program in the cartridge won’t run.
+        check(checksum::verify_header_checksum(&self.rom_data))?;
+
+        let header_size = self.header.rom_size.bytes();
+        check(match header_size == self.rom_size {
+            true  => Ok(()),
+            false => Err(CartError::SizeMismatch { header : header_size, actual : self.rom_size }),
+        })?;
+
+        self.mapper = match mapper::from_header(&self.header, &self.rom_data) {
+            Some(mapper) => mapper,
+            None => {
+                check(Err(CartError::UnsupportedMapper(self.header.cart_type)))?;
+                Box::new(RomOnly::new(0, false))
+            },
         };
+
+        // nothing checks the global checksum on real hardware, so it is only ever a warning
+        if let Err(err) = checksum::verify_global_checksum(&self.rom_data) {
+            warnings.push(err);
+        }
+        self.warnings = warnings;
+
+        Ok(())
+    }
+    pub fn print_info(&self) {
         println!("Cartridge Loaded");
         println!("\t Title          : {}", self.header.title);
-        println!("\t Cartridge Type : {:#04X} ({})", self.header.cart_type, cart_type);
-        println!("\t SGB Support    : {}", if self.header.sgb_flag == 0x03 {"Yes"} else {"No"});
-        println!("\t ROM Size       : {:#04X} ({})", self.header.rom_size, rom_size);
-        println!("\t RAM Size       : {:#04X} ({})", self.header.ram_size, ram_size);
-        println!("\t LIC Code       : {:#04X} ({})", self.header.lic_code, lic_name);
-
-        let mut checksum : u8 = 0;
-        for address in 0x134..=0x14C {
-            checksum = checksum.wrapping_sub(self.rom_data[address]).wrapping_sub(1);
-        }
-        // If the byte at $014D does not match the lower 8 bits of checksum, 
-        // the boot ROM will lock up and the program in the cartridge won’t run.
-        assert_eq!(self.header.checksum, (checksum & 0xFF), "Checksum FAILED"); // will panic if false
-        println!("Checksum PASSED");
+        println!("\t Cartridge Type : {:#04X} ({})", self.header.cart_type.code(), self.header.cart_type.name());
+        println!("\t Manufacturer   : {}", self.header.manufacturer);
+        println!("\t CGB Support    : {:?}", self.header.cgb_flag);
+        println!("\t SGB Support    : {}", if self.header.sgb_supported() {"Yes"} else {"No"});
+        println!("\t Logo           : {}", if self.header.logo_valid() {"Valid"} else {"Invalid"});
+        println!("\t ROM Size       : {:#04X} ({})", self.header.rom_size.code(), human_readable(self.header.rom_size.bytes()));
+        println!("\t RAM Size       : {:#04X} ({})", self.header.ram_size.code(), self.header.ram_size.name());
+        println!("\t LIC Code       : {:#04X} ({})", self.header.licensee.code(), self.header.licensee.name());
 
         //println!("{0:?}", self.header);
-
-        Ok(())
+    }
+    // replaces the mapper picked from the header, for hardware this crate doesn't know about
+    pub fn set_mapper(&mut self, mapper : Box<dyn Mapper>) {
+        self.mapper = mapper;
     }
     pub fn read(&self, address : u16)  -> u8 {
-        self.rom_data[address as usize]
+        self.mapper.read_rom(&self.rom_data, address)
     }
+    // ROM is read-only, writes are handled by the bank controller
     pub fn write(&mut self, address : u16, value : u8) {
-        self.rom_data[address as usize] = value
+        self.mapper.write_rom(address, value)
+    }
+    // external RAM (0xA000 - 0xBFFF)
+    pub fn read_ram(&self, address : u16) -> u8 {
+        self.mapper.read_ram(address)
+    }
+    pub fn write_ram(&mut self, address : u16, value : u8) {
+        self.mapper.write_ram(address, value)
+    }
+    // advances cartridge hardware that runs on its own clock
+    pub fn tick(&mut self, cycles : u32) {
+        self.mapper.tick(cycles)
+    }
+    // battery backed RAM to persist between sessions
+    pub fn save_ram(&self) -> Option<Vec<u8>> {
+        self.mapper.save_ram()
+    }
+    pub fn load_ram(&mut self, data : &[u8]) {
+        self.mapper.load_ram(data)
+    }
+    // restores battery RAM from the save file, a missing file leaves the RAM blank
+    pub fn load_save(&mut self) -> std::io::Result<()> {
+        let Some(path) = self.save_path.as_ref() else { return Ok(()) };
+        if self.mapper.save_ram().is_none() {
+            return Ok(());
+        }
+        match std::fs::read(path) {
+            Ok(data) => {
+                self.mapper.load_ram(&data);
+                self.saved = Some(data);
+                Ok(())
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+    // writes battery RAM to the save file when it changed since the last flush
+    pub fn flush_save(&mut self) -> std::io::Result<()> {
+        let (Some(path), Some(data)) = (self.save_path.as_ref(), self.mapper.save_ram()) else {
+            return Ok(());
+        };
+        if self.saved.as_ref() == Some(&data) {
+            return Ok(());
+        }
+        std::fs::write(path, &data)?;
+        self.saved = Some(data);
+        Ok(())
+    }
+    // state of the rumble motor, always off without one
+    pub fn rumble(&self) -> bool {
+        self.mapper.rumble()
+    }
+    // selects what drives the MBC3 real-time clock, no-op for other cartridges
+    pub fn set_rtc_clock(&mut self, clock : RtcClock) {
+        self.mapper.set_rtc_clock(clock)
     }
 }
@@ -66,20 +66,18 @@ pub enum InstructionType {
     Cpl,  // complement A register (Flip all bits)
     Daa,  // decimal adjust register A
 
-    /*
     /// Prefix Instructions
-    Bit(),
-    Res(),
-    Set(),
-    Srl(),
-    Rr(),
-    Rl(),
-    Rrc(),
-    Rlc(),
-    Sra(),
-    Sla(),
-    Swap(),
-    */
+    Bit(u8, Target), // test bit n of target
+    Res(u8, Target), // reset bit n of target
+    Set(u8, Target), // set bit n of target
+    Srl(Target),     // shift target right into carry, bit 7 reset
+    Rr(Target),      // rotate target right through carry flag
+    Rl(Target),      // rotate target left through carry flag
+    Rrc(Target),     // rotate target right
+    Rlc(Target),     // rotate target left
+    Sra(Target),     // shift target right into carry, bit 7 unchanged
+    Sla(Target),     // shift target left into carry, bit 0 reset
+    Swap(Target),    // swap upper and lower nibbles of target
 
     /// Jump Instructions
     Jp(JumpTest, Source),  // jump to source address if jumptest is valid
@@ -96,7 +94,7 @@ pub enum InstructionType {
     Push(Reg16),
     Pop(Reg16),
     Call(JumpTest, Source), // call source address if jumptest is valid
-    Rst(Source),            // restart program flow to source address
+    Rst(u8),                // restart program flow to the fixed vector address
     Ret(JumpTest),          // return if jumptest is true
     Reti,                   // return and enable interrupts
 
@@ -109,6 +107,9 @@ pub enum InstructionType {
 }
 
 impl InstructionType {
+    // returns the instruction along with its duration in T-cycles;
+    // conditional jumps, calls and returns report the not-taken duration,
+    // the extra cycles of a taken branch are accounted for on execution
     pub fn from_byte(opcode : u8) -> Option<(InstructionType, u8)> {
         match opcode {
             // NOP
@@ -148,7 +149,7 @@ impl InstructionType {
             // LD DE, nn
             0x11 => Some((InstructionType::Load(Target::WordReg(Reg16::De), Source::WordConst), 12)),
             // LD (DE), A
-            0x12 => Some((InstructionType::Load(Target::Deref(Addr::WordReg(Reg16::Bc)), Source::ByteReg(Reg8::A)), 8)),
+            0x12 => Some((InstructionType::Load(Target::Deref(Addr::WordReg(Reg16::De)), Source::ByteReg(Reg8::A)), 8)),
             // INC DE
             0x13 => Some((InstructionType::Inc(Target::WordReg(Reg16::De)), 8)),
             // INC D
@@ -160,7 +161,7 @@ impl InstructionType {
             // RLA
             0x17 => Some((InstructionType::Rla, 4)),
             // JR n
-            0x18 => Some((InstructionType::Jr(JumpTest::Always, Source::ByteConst), 8)),
+            0x18 => Some((InstructionType::Jr(JumpTest::Always, Source::ByteConst), 12)),
             // ADD HL, DE
             0x19 => Some((InstructionType::Add(Target::WordReg(Reg16::Hl), Source::WordReg(Reg16::De)), 8)),
             // LD A, (DE)
@@ -179,6 +180,8 @@ impl InstructionType {
             0x20 => Some((InstructionType::Jr(JumpTest::NotZero, Source::ByteConst), 8)),
             // LD HL, nn
             0x21 => Some((InstructionType::Load(Target::WordReg(Reg16::Hl), Source::WordConst), 12)),
+            // LDI (HL), A
+            0x22 => Some((InstructionType::LoadI(Target::Deref(Addr::WordReg(Reg16::Hl)), Source::ByteReg(Reg8::A)), 8)),
             // INC HL
             0x23 => Some((InstructionType::Inc(Target::WordReg(Reg16::Hl)), 8)),
             // INC H
@@ -189,7 +192,7 @@ impl InstructionType {
             0x26 => Some((InstructionType::Load(Target::ByteReg(Reg8::H), Source::ByteConst), 8)),
             // DAA
             0x27 => Some((InstructionType::Daa, 4)),
-            // JR NZ, n
+            // JR Z, n
             0x28 => Some((InstructionType::Jr(JumpTest::Zero, Source::ByteConst), 8)),
             // ADD HL, HL
             0x29 => Some((InstructionType::Add(Target::WordReg(Reg16::Hl), Source::WordReg(Reg16::Hl)), 8)),
@@ -329,7 +332,7 @@ impl InstructionType {
             0x6C => Some((InstructionType::Load(Target::ByteReg(Reg8::L), Source::ByteReg(Reg8::H)), 4)),
             // LD L, L
             0x6D => Some((InstructionType::Nop, 4)),
-            // LD H, (HL)
+            // LD L, (HL)
             0x6E => Some((InstructionType::Load(Target::ByteReg(Reg8::L), Source::Deref(Addr::WordReg(Reg16::Hl))), 8)),
             // LD L, A
             0x6F => Some((InstructionType::Load(Target::ByteReg(Reg8::L), Source::ByteReg(Reg8::A)), 4)),
@@ -500,7 +503,7 @@ impl InstructionType {
             // JP NZ, nn
             0xC2 => Some((InstructionType::Jp(JumpTest::NotZero, Source::WordConst), 12)),
             // JP nn
-            0xC3 => Some((InstructionType::Jp(JumpTest::Always, Source::WordConst), 12)),
+            0xC3 => Some((InstructionType::Jp(JumpTest::Always, Source::WordConst), 16)),
             // CALL NZ, nn
             0xC4 => Some((InstructionType::Call(JumpTest::NotZero, Source::WordConst), 12)),
             // PUSH BC
@@ -508,17 +511,17 @@ impl InstructionType {
             // ADD A, n
             0xC6 => Some((InstructionType::Add(Target::ByteReg(Reg8::A), Source::ByteConst), 8)),
             // RST n
-            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some((InstructionType::Rst(Source::ByteConst), 32)),
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some((InstructionType::Rst(opcode & 0x38), 16)),
             // RET Z
             0xC8 => Some((InstructionType::Ret(JumpTest::Zero), 8)),
             // RET
-            0xC9 => Some((InstructionType::Ret(JumpTest::Always), 8)),
+            0xC9 => Some((InstructionType::Ret(JumpTest::Always), 16)),
             // JP Z, nn
             0xCA => Some((InstructionType::Jp(JumpTest::Zero, Source::WordConst), 12)),
             // CALL Z, nn
             0xCC => Some((InstructionType::Call(JumpTest::Zero, Source::WordConst), 12)),
             // CALL nn
-            0xCD => Some((InstructionType::Call(JumpTest::Always, Source::WordConst), 12)),
+            0xCD => Some((InstructionType::Call(JumpTest::Always, Source::WordConst), 24)),
             // ADC A, n
             0xCE => Some((InstructionType::Adc(Target::ByteReg(Reg8::A), Source::ByteConst), 8)),
             // RET NC
@@ -536,7 +539,7 @@ impl InstructionType {
             // RET C
             0xD8 => Some((InstructionType::Ret(JumpTest::Carry), 8)),
             // RETI
-            0xD9 => Some((InstructionType::Reti, 8)),
+            0xD9 => Some((InstructionType::Reti, 16)),
             // JP C, nn
             0xDA => Some((InstructionType::Jp(JumpTest::Carry, Source::WordConst), 12)),
             // CALL C, nn
@@ -587,7 +590,57 @@ impl InstructionType {
             _    => None
         }
     }
-    pub fn from_byte_prefixed(_opcode : u8) -> Option<(InstructionType, u8)> {
-        todo!("Add Gameboy Color support")
+    pub fn from_byte_prefixed(opcode : u8) -> Option<(InstructionType, u8)> {
+        // the CB table is fully regular:
+        // bits 0-2 select the operand (B, C, D, E, H, L, (HL), A),
+        // bits 3-5 select the bit index or the shift/rotate operation,
+        // bits 6-7 select the instruction group (shift/rotate, BIT, RES, SET)
+        let target = match opcode & 0x07 {
+            0x00 => Target::ByteReg(Reg8::B),
+            0x01 => Target::ByteReg(Reg8::C),
+            0x02 => Target::ByteReg(Reg8::D),
+            0x03 => Target::ByteReg(Reg8::E),
+            0x04 => Target::ByteReg(Reg8::H),
+            0x05 => Target::ByteReg(Reg8::L),
+            0x06 => Target::Deref(Addr::WordReg(Reg16::Hl)),
+            _    => Target::ByteReg(Reg8::A),
+        };
+        let bit = (opcode >> 3) & 0x07;
+        let is_hl = opcode & 0x07 == 0x06;
+
+        let instruction = match opcode >> 6 {
+            0x00 => match bit {
+                // RLC r
+                0x00 => InstructionType::Rlc(target),
+                // RRC r
+                0x01 => InstructionType::Rrc(target),
+                // RL r
+                0x02 => InstructionType::Rl(target),
+                // RR r
+                0x03 => InstructionType::Rr(target),
+                // SLA r
+                0x04 => InstructionType::Sla(target),
+                // SRA r
+                0x05 => InstructionType::Sra(target),
+                // SWAP r
+                0x06 => InstructionType::Swap(target),
+                // SRL r
+                _    => InstructionType::Srl(target),
+            },
+            // BIT b, r
+            0x01 => InstructionType::Bit(bit, target),
+            // RES b, r
+            0x02 => InstructionType::Res(bit, target),
+            // SET b, r
+            _    => InstructionType::Set(bit, target),
+        };
+
+        // (HL) operands take extra memory accesses; BIT only reads it back
+        let cycles = match (instruction, is_hl) {
+            (_, false)                        => 8,
+            (InstructionType::Bit(..), true)  => 12,
+            (_, true)                         => 16,
+        };
+        Some((instruction, cycles))
     }
 }
\ No newline at end of file
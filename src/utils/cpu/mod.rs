@@ -16,10 +16,12 @@ use super::{
     memory::{*},
 };
 
+// T-cycles per second in normal speed
+pub const CPU_CLOCK : u32 = 4_194_304;
+
 enum OperandType {
     Byte(u8),
     Word(u16),
-    Null,
 }
 use OperandType::*;
 
@@ -28,166 +30,681 @@ pub struct Cpu {
     pub mmu    : Mmu,  // memory management unit
     pub ime    : bool, // interrupt master enable
     pub halted : bool,
+    pub hung   : bool, // an unused opcode froze the cpu until reset
+    ei_delay   : bool, // EI takes effect after the following instruction
+    halt_bug   : bool, // next opcode byte is read twice
 }
 
 impl Cpu {
-    pub fn new(cartridge : &CartContext) -> Self {
+    pub fn new(cartridge : CartContext) -> Self {
+        let mmu = Mmu::new(cartridge);
         Cpu {
-            regs   : Registers::new(),
-            mmu    : Mmu::new(cartridge),
-            ime    : true,
+            regs   : if mmu.cgb { Registers::new_cgb() } else { Registers::new() },
+            mmu,
+            ime    : false,
             halted : false,
+            hung   : false,
+            ei_delay : false,
+            halt_bug : false,
         }
     }
     fn set_pc(&mut self, address : u16) { self.regs.pc = address; }
     fn inc_pc_by(&mut self, val : u16) { self.set_pc(self.regs.pc.wrapping_add(val)); }
     fn get_pc(&self) -> u16 { self.regs.pc }
 
-    fn fetch_byte(&self, address : u16) -> OperandType { 
-        OperandType::Byte(self.mmu.fetch_byte(address)) 
+    fn fetch_byte(&self, address : u16) -> u8 { self.mmu.fetch_byte(address) }
+    fn fetch_word(&self, address : u16) -> u16 { self.mmu.fetch_word(address) }
+    fn set_byte(&mut self, addr : u16, val : u8) { self.mmu.set_byte(addr, val); }
+    fn set_word(&mut self, addr : u16, val : u16) { self.mmu.set_word(addr, val); }
+
+    fn fetch_opcode(&mut self) -> u8 {
+        let opcode = self.fetch_byte(self.get_pc());
+        match self.halt_bug {
+            true  => self.halt_bug = false, // PC fails to increment
+            false => self.inc_pc_by(1),
+        }
+        opcode
     }
-    fn fetch_word(&self, address : u16) -> OperandType { 
-        OperandType::Word(self.mmu.fetch_word(address)) 
+    // reads the immediate operands following the opcode
+    fn fetch_imm_byte(&mut self) -> u8 {
+        let value = self.fetch_byte(self.get_pc());
+        self.inc_pc_by(1); value
+    }
+    fn fetch_imm_word(&mut self) -> u16 {
+        let value = self.fetch_word(self.get_pc());
+        self.inc_pc_by(2); value
+    }
+
+    fn get_reg8(&self, reg8 : Reg8) -> u8 {
+        match reg8 {
+            Reg8::A => self.regs.a,
+            Reg8::B => self.regs.b,
+            Reg8::C => self.regs.c,
+            Reg8::D => self.regs.d,
+            Reg8::E => self.regs.e,
+            Reg8::H => self.regs.h,
+            Reg8::L => self.regs.l,
+        }
+    }
+    fn set_reg8(&mut self, reg8 : Reg8, value : u8) {
+        match reg8 {
+            Reg8::A => self.regs.a = value,
+            Reg8::B => self.regs.b = value,
+            Reg8::C => self.regs.c = value,
+            Reg8::D => self.regs.d = value,
+            Reg8::E => self.regs.e = value,
+            Reg8::H => self.regs.h = value,
+            Reg8::L => self.regs.l = value,
+        }
+    }
+    fn get_reg16(&self, reg16 : Reg16) -> u16 {
+        match reg16 {
+            Reg16::Af => self.regs.af(),
+            Reg16::Bc => self.regs.bc(),
+            Reg16::De => self.regs.de(),
+            Reg16::Hl => self.regs.hl(),
+            Reg16::Sp => self.regs.sp,
+        }
+    }
+    fn set_reg16(&mut self, reg16 : Reg16, value : u16) {
+        match reg16 {
+            Reg16::Af => self.regs.set_af(value),
+            Reg16::Bc => self.regs.set_bc(value),
+            Reg16::De => self.regs.set_de(value),
+            Reg16::Hl => self.regs.set_hl(value),
+            Reg16::Sp => self.regs.sp = value,
+        }
     }
-    fn set_byte(&mut self, addr : u16, val : u8) { self.mmu.set_byte(addr, val); }
 
-    pub fn run(&mut self) -> Result<(), ()> {
+    // computes the effective address of a memory operand,
+    // consuming any immediate bytes it requires
+    fn resolve_addr(&mut self, addr : Addr) -> u16 {
+        match addr {
+            Addr::WordReg(reg16) => self.get_reg16(reg16),              // (reg16)
+            Addr::WordConst      => self.fetch_imm_word(),              // (nn)
+            Addr::RegRel(reg8)   => 0xFF00 | self.get_reg8(reg8) as u16, // (0xFF00 + reg8)
+            Addr::ByteRel        => 0xFF00 | self.fetch_imm_byte() as u16, // (0xFF00 + n)
+        }
+    }
 
-        loop {
-            if self.halted {
-                break;
+    fn read_operand(&mut self, src : Source) -> OperandType {
+        match src {
+            Source::ByteConst      => Byte(self.fetch_imm_byte()),  // n
+            Source::WordConst      => Word(self.fetch_imm_word()),  // nn
+            Source::ByteReg(reg8)  => Byte(self.get_reg8(reg8)),    // reg8
+            Source::WordReg(reg16) => Word(self.get_reg16(reg16)),  // reg16
+            Source::Deref(addr)    => {                             // (addr)
+                let addr = self.resolve_addr(addr);
+                Byte(self.fetch_byte(addr))
             }
-            // fetch
-            let opcode = if let Byte(value) = self.fetch_byte(self.get_pc()) {
-                self.inc_pc_by(1); value
-            } else {
-                panic!("Cannot retrieve data from memory")
-            };
-
-            // decode
-            let (instruction, _cycles) = if let Some((instr, cycles)) = match opcode {
-
-                    0xcb => InstructionType::from_byte_prefixed(opcode),
-                    _ => InstructionType::from_byte(opcode),
-
-            } {(instr, cycles) } else { panic!("Opcode {:#02X} is not valid", opcode) };
-
-            // execute
-            match instruction {
-
-                Load(dest, src) => {
-
-                    let operand = match src {
-
-                        Source::ByteConst => { // LD .., n
-                            let imm_bconst = self.fetch_byte(self.get_pc());
-                            self.inc_pc_by(1); imm_bconst
-                        },
-                        Source::WordConst => { // LD .., nn
-                            let imm_wconst = self.fetch_word(self.get_pc());
-                            self.inc_pc_by(2); imm_wconst
-                        }
-                        Source::ByteReg(reg8) => { // LD .., reg8
-                            Byte(match reg8 {
-                                Reg8::A => self.regs.a,
-                                Reg8::B => self.regs.b,
-                                Reg8::C => self.regs.c,
-                                Reg8::D => self.regs.d,
-                                Reg8::E => self.regs.e,
-                                Reg8::H => self.regs.h,
-                                Reg8::L => self.regs.l,
-                            })
-                        },
-                        Source::WordReg(reg16) => { // LD .., reg16
-                            Word(match reg16 {
-                                Reg16::Bc => self.regs.bc(),
-                                Reg16::De => self.regs.de(),
-                                Reg16::Hl => self.regs.hl(),
-                                other => panic!("Unexpected operand {:?}", other)
-                            })
-                        },
-                        Source::Deref(Addr::WordReg(Reg16::Hl)) => { // LD .., (HL)
-                            self.fetch_byte(self.regs.hl())
-                        },
-                        Source::Deref(Addr::WordConst) => {  // LD .., (nn)
-                            let imm_addr = self.fetch_word(self.get_pc());
-                            self.inc_pc_by(2);
-                            if let Word(imm_addr) = imm_addr { self.fetch_byte(imm_addr) } 
-                            else { Null }
-                        }
-                        _ => todo!()
-                    };
-
-                    if let OperandType::Byte(value) = operand {
-
-                        let target : Option<&mut u8> = match dest {
-
-                            Target::ByteReg(reg8) => { // LD reg8, ..
-                                Some(match reg8 {
-                                    Reg8::A => &mut self.regs.a,
-                                    Reg8::B => &mut self.regs.b,
-                                    Reg8::C => &mut self.regs.c,
-                                    Reg8::D => &mut self.regs.d,
-                                    Reg8::E => &mut self.regs.e,
-                                    Reg8::H => &mut self.regs.h,
-                                    Reg8::L => &mut self.regs.l,
-                                })
-                            },
-                            Target::Deref(Addr::WordReg(Reg16::Hl)) => { // LD (HL), ..
-                                self.set_byte(self.regs.hl(), value); 
-                                None
-                            },
-                            Target::Deref(Addr::WordConst) => { // LD (nn), ..
-                                let imm_addr = self.fetch_word(self.get_pc());
-                                self.inc_pc_by(2); 
-                                if let Word(imm_addr) = imm_addr { 
-                                    self.set_byte(imm_addr, value); 
-                                }; None
-                            }
-                            _ => todo!(),
-                        };
-
-                        if let Some(target) = target { *target = value; }
-
-                    } else if let OperandType::Word(value) = operand {
-                        
-                        if let Target::WordReg(reg16) = dest { // LD reg16, ..
-                            
-                            match reg16 {
-                                Reg16::Bc => self.regs.set_bc(value),
-                                Reg16::De => self.regs.set_de(value),
-                                Reg16::Hl => self.regs.set_hl(value),
-                                Reg16::Sp => {
-                                    self.regs.sp = value;
-                                },
-                                other => panic!("Unexpected target {:?}", other)
-                            }
-                        }
-                    }
-                },
-                Halt => {
-                    self.halted = true;
-                },
-                _other => todo!(),
+        }
+    }
+    fn write_operand(&mut self, dest : Target, operand : OperandType) {
+        match (dest, operand) {
+            (Target::ByteReg(reg8), Byte(value))   => self.set_reg8(reg8, value),
+            (Target::WordReg(reg16), Word(value))  => self.set_reg16(reg16, value),
+            (Target::Deref(addr), Byte(value))     => {
+                let addr = self.resolve_addr(addr);
+                self.set_byte(addr, value);
+            },
+            (Target::Deref(addr), Word(value))     => { // LD (nn), SP
+                let addr = self.resolve_addr(addr);
+                self.set_word(addr, value);
+            },
+            (other, _) => panic!("Unexpected target {:?}", other)
+        }
+    }
+    fn read_byte_source(&mut self, src : Source) -> u8 {
+        match self.read_operand(src) {
+            Byte(value) => value,
+            Word(_) => panic!("Unexpected operand {:?}", src)
+        }
+    }
+    // 8-bit read-modify-write targets (registers and (HL))
+    fn read_byte_target(&mut self, dest : Target) -> u8 {
+        match dest {
+            Target::ByteReg(reg8) => self.get_reg8(reg8),
+            Target::Deref(addr)   => {
+                let addr = self.resolve_addr(addr);
+                self.fetch_byte(addr)
+            },
+            other => panic!("Unexpected target {:?}", other)
+        }
+    }
+
+    fn push_word(&mut self, value : u16) {
+        self.regs.sp = self.regs.sp.wrapping_sub(2);
+        self.set_word(self.regs.sp, value);
+    }
+    fn pop_word(&mut self) -> u16 {
+        let value = self.fetch_word(self.regs.sp);
+        self.regs.sp = self.regs.sp.wrapping_add(2);
+        value
+    }
+
+    // extra cycles spent when a conditional branch is taken
+    fn branch_cycles(test : JumpTest, extra : u32) -> u32 {
+        match test {
+            JumpTest::Always => 0, // already included in the decoded cycles
+            _ => extra,
+        }
+    }
+
+    fn check_condition(&self, test : JumpTest) -> bool {
+        match test {
+            JumpTest::Zero     =>  self.regs.get_flag(Z),
+            JumpTest::NotZero  => !self.regs.get_flag(Z),
+            JumpTest::Carry    =>  self.regs.get_flag(C),
+            JumpTest::NotCarry => !self.regs.get_flag(C),
+            JumpTest::Always   => true,
+        }
+    }
+
+    /// Arithmetic Logic Unit
+    fn alu_add(&mut self, value : u8, use_carry : bool) {
+        let carry = (use_carry && self.regs.get_flag(C)) as u8;
+        let a = self.regs.a;
+        let result = a.wrapping_add(value).wrapping_add(carry);
+
+        self.regs.set_flag(Z, result == 0);
+        self.regs.set_flag(N, false);
+        self.regs.set_flag(H, (a & 0x0F) + (value & 0x0F) + carry > 0x0F);
+        self.regs.set_flag(C, (a as u16) + (value as u16) + (carry as u16) > 0xFF);
+        self.regs.a = result;
+    }
+    fn alu_sub(&mut self, value : u8, use_carry : bool) -> u8 {
+        let carry = (use_carry && self.regs.get_flag(C)) as u8;
+        let a = self.regs.a;
+        let result = a.wrapping_sub(value).wrapping_sub(carry);
+
+        self.regs.set_flag(Z, result == 0);
+        self.regs.set_flag(N, true);
+        self.regs.set_flag(H, (a & 0x0F) < (value & 0x0F) + carry);
+        self.regs.set_flag(C, (a as u16) < (value as u16) + (carry as u16));
+        result
+    }
+    fn alu_and(&mut self, value : u8) {
+        self.regs.a &= value;
+        self.regs.set_flag(Z, self.regs.a == 0);
+        self.regs.set_flag(N, false);
+        self.regs.set_flag(H, true);
+        self.regs.set_flag(C, false);
+    }
+    fn alu_or(&mut self, value : u8) {
+        self.regs.a |= value;
+        self.regs.set_flag(Z, self.regs.a == 0);
+        self.regs.set_flag(N, false);
+        self.regs.set_flag(H, false);
+        self.regs.set_flag(C, false);
+    }
+    fn alu_xor(&mut self, value : u8) {
+        self.regs.a ^= value;
+        self.regs.set_flag(Z, self.regs.a == 0);
+        self.regs.set_flag(N, false);
+        self.regs.set_flag(H, false);
+        self.regs.set_flag(C, false);
+    }
+    fn alu_inc(&mut self, value : u8) -> u8 { // carry flag is not affected
+        let result = value.wrapping_add(1);
+        self.regs.set_flag(Z, result == 0);
+        self.regs.set_flag(N, false);
+        self.regs.set_flag(H, (value & 0x0F) == 0x0F);
+        result
+    }
+    fn alu_dec(&mut self, value : u8) -> u8 { // carry flag is not affected
+        let result = value.wrapping_sub(1);
+        self.regs.set_flag(Z, result == 0);
+        self.regs.set_flag(N, true);
+        self.regs.set_flag(H, (value & 0x0F) == 0x00);
+        result
+    }
+    fn alu_add_hl(&mut self, value : u16) { // zero flag is not affected
+        let hl = self.regs.hl();
+        self.regs.set_flag(N, false);
+        self.regs.set_flag(H, (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF);
+        self.regs.set_flag(C, (hl as u32) + (value as u32) > 0xFFFF);
+        self.regs.set_hl(hl.wrapping_add(value));
+    }
+    fn alu_add_sp(&mut self, offset : u8) -> u16 { // SP + signed offset, flags from the low byte
+        let sp = self.regs.sp;
+        let value = offset as i8 as i16 as u16;
+        self.regs.set_flag(Z, false);
+        self.regs.set_flag(N, false);
+        self.regs.set_flag(H, (sp & 0x000F) + (value & 0x000F) > 0x000F);
+        self.regs.set_flag(C, (sp & 0x00FF) + (value & 0x00FF) > 0x00FF);
+        sp.wrapping_add(value)
+    }
+    fn alu_rlc(&mut self, value : u8) -> u8 { // rotate left, bit 7 to carry
+        let result = value.rotate_left(1);
+        self.set_shift_flags(result, value & 0x80 != 0);
+        result
+    }
+    fn alu_rrc(&mut self, value : u8) -> u8 { // rotate right, bit 0 to carry
+        let result = value.rotate_right(1);
+        self.set_shift_flags(result, value & 0x01 != 0);
+        result
+    }
+    fn alu_rl(&mut self, value : u8) -> u8 { // rotate left through carry
+        let result = (value << 1) | self.regs.get_flag(C) as u8;
+        self.set_shift_flags(result, value & 0x80 != 0);
+        result
+    }
+    fn alu_rr(&mut self, value : u8) -> u8 { // rotate right through carry
+        let result = (value >> 1) | ((self.regs.get_flag(C) as u8) << 7);
+        self.set_shift_flags(result, value & 0x01 != 0);
+        result
+    }
+    fn alu_sla(&mut self, value : u8) -> u8 { // shift left, bit 0 reset
+        let result = value << 1;
+        self.set_shift_flags(result, value & 0x80 != 0);
+        result
+    }
+    fn alu_sra(&mut self, value : u8) -> u8 { // shift right, bit 7 unchanged
+        let result = (value >> 1) | (value & 0x80);
+        self.set_shift_flags(result, value & 0x01 != 0);
+        result
+    }
+    fn alu_srl(&mut self, value : u8) -> u8 { // shift right, bit 7 reset
+        let result = value >> 1;
+        self.set_shift_flags(result, value & 0x01 != 0);
+        result
+    }
+    fn alu_swap(&mut self, value : u8) -> u8 {
+        let result = value.rotate_left(4);
+        self.set_shift_flags(result, false);
+        result
+    }
+    fn alu_bit(&mut self, bit : u8, value : u8) { // carry flag is not affected
+        self.regs.set_flag(Z, value & (1 << bit) == 0);
+        self.regs.set_flag(N, false);
+        self.regs.set_flag(H, true);
+    }
+    fn set_shift_flags(&mut self, result : u8, carry : bool) {
+        self.regs.set_flag(Z, result == 0);
+        self.regs.set_flag(N, false);
+        self.regs.set_flag(H, false);
+        self.regs.set_flag(C, carry);
+    }
+    fn alu_daa(&mut self) {
+        // https://gbdev.io/pandocs/CPU_Instruction_Set.html (DAA)
+        let mut a = self.regs.a;
+        let mut carry = self.regs.get_flag(C);
+
+        if !self.regs.get_flag(N) { // after an addition
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.regs.get_flag(H) || (a & 0x0F) > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {                    // after a subtraction
+            if carry {
+                a = a.wrapping_sub(0x60);
             }
+            if self.regs.get_flag(H) {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+        self.regs.set_flag(Z, a == 0);
+        self.regs.set_flag(H, false);
+        self.regs.set_flag(C, carry);
+        self.regs.a = a;
+    }
+
+    // executes the instruction, returning the extra cycles of taken branches
+    fn execute(&mut self, instruction : InstructionType) -> u32 {
+
+        match instruction {
+
+            Load(dest, src) => { // LD target, source
+                let operand = self.read_operand(src);
+                self.write_operand(dest, operand);
+            },
+            LoadI(dest, src) => { // LD (HL+), A | LD A, (HL+)
+                let operand = self.read_operand(src);
+                self.write_operand(dest, operand);
+                self.regs.set_hl(self.regs.hl().wrapping_add(1));
+            },
+            LoadD(dest, src) => { // LD (HL-), A | LD A, (HL-)
+                let operand = self.read_operand(src);
+                self.write_operand(dest, operand);
+                self.regs.set_hl(self.regs.hl().wrapping_sub(1));
+            },
+            LoadH(dest, src) => { // LDH (n), A | LDH A, (n) | LD (C), A | LD A, (C)
+                let operand = self.read_operand(src);
+                self.write_operand(dest, operand);
+            },
+            LoadHL(_, src) => { // LD HL, SP + n
+                let offset = self.read_byte_source(src);
+                let value = self.alu_add_sp(offset);
+                self.regs.set_hl(value);
+            },
+
+            Inc(Target::WordReg(reg16)) => { // INC reg16 (no flags affected)
+                self.set_reg16(reg16, self.get_reg16(reg16).wrapping_add(1));
+            },
+            Dec(Target::WordReg(reg16)) => { // DEC reg16 (no flags affected)
+                self.set_reg16(reg16, self.get_reg16(reg16).wrapping_sub(1));
+            },
+            Inc(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_inc(value);
+                self.write_operand(dest, Byte(result));
+            },
+            Dec(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_dec(value);
+                self.write_operand(dest, Byte(result));
+            },
+
+            Add(Target::WordReg(Reg16::Hl), Source::WordReg(reg16)) => { // ADD HL, reg16
+                self.alu_add_hl(self.get_reg16(reg16));
+            },
+            Add(Target::WordReg(Reg16::Sp), src) => { // ADD SP, n
+                let offset = self.read_byte_source(src);
+                self.regs.sp = self.alu_add_sp(offset);
+            },
+            Add(_, src) => { // ADD A, ..
+                let value = self.read_byte_source(src);
+                self.alu_add(value, false);
+            },
+            Adc(_, src) => { // ADC A, ..
+                let value = self.read_byte_source(src);
+                self.alu_add(value, true);
+            },
+            Sub(src) => {
+                let value = self.read_byte_source(src);
+                self.regs.a = self.alu_sub(value, false);
+            },
+            Sbc(src) => {
+                let value = self.read_byte_source(src);
+                self.regs.a = self.alu_sub(value, true);
+            },
+            And(src) => {
+                let value = self.read_byte_source(src);
+                self.alu_and(value);
+            },
+            Or(src) => {
+                let value = self.read_byte_source(src);
+                self.alu_or(value);
+            },
+            Xor(src) => {
+                let value = self.read_byte_source(src);
+                self.alu_xor(value);
+            },
+            Cp(src) => { // same as SUB, but the result is thrown away
+                let value = self.read_byte_source(src);
+                self.alu_sub(value, false);
+            },
+
+            Ccf => {
+                self.regs.set_flag(N, false);
+                self.regs.set_flag(H, false);
+                self.regs.set_flag(C, !self.regs.get_flag(C));
+            },
+            Scf => {
+                self.regs.set_flag(N, false);
+                self.regs.set_flag(H, false);
+                self.regs.set_flag(C, true);
+            },
+            Cpl => {
+                self.regs.a = !self.regs.a;
+                self.regs.set_flag(N, true);
+                self.regs.set_flag(H, true);
+            },
+            Daa => self.alu_daa(),
+
+            // accumulator rotates always reset the zero flag
+            Rlca => {
+                self.regs.a = self.alu_rlc(self.regs.a);
+                self.regs.set_flag(Z, false);
+            },
+            Rrca => {
+                self.regs.a = self.alu_rrc(self.regs.a);
+                self.regs.set_flag(Z, false);
+            },
+            Rla => {
+                self.regs.a = self.alu_rl(self.regs.a);
+                self.regs.set_flag(Z, false);
+            },
+            Rra => {
+                self.regs.a = self.alu_rr(self.regs.a);
+                self.regs.set_flag(Z, false);
+            },
+
+            Jp(test, Source::WordConst) => { // JP cc, nn
+                let address = self.fetch_imm_word();
+                if self.check_condition(test) {
+                    self.set_pc(address);
+                    return Self::branch_cycles(test, 4);
+                }
+            },
+            Jp(_, _) => { // JP HL
+                self.set_pc(self.regs.hl());
+            },
+            Jr(test, src) => { // JR cc, n
+                let offset = self.read_byte_source(src) as i8;
+                if self.check_condition(test) {
+                    self.set_pc(self.get_pc().wrapping_add(offset as u16));
+                    return Self::branch_cycles(test, 4);
+                }
+            },
+
+            Push(reg16) => self.push_word(self.get_reg16(reg16)),
+            Pop(reg16)  => {
+                let value = self.pop_word();
+                self.set_reg16(reg16, value);
+            },
+            Call(test, _) => { // CALL cc, nn
+                let address = self.fetch_imm_word();
+                if self.check_condition(test) {
+                    self.push_word(self.get_pc());
+                    self.set_pc(address);
+                    return Self::branch_cycles(test, 12);
+                }
+            },
+            Rst(vector) => {
+                self.push_word(self.get_pc());
+                self.set_pc(vector as u16);
+            },
+            Ret(test) => {
+                if self.check_condition(test) {
+                    let address = self.pop_word();
+                    self.set_pc(address);
+                    return Self::branch_cycles(test, 12);
+                }
+            },
+            Reti => { // unlike EI, interrupts are enabled immediately
+                let address = self.pop_word();
+                self.set_pc(address);
+                self.ime = true;
+            },
+
+            Rlc(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_rlc(value);
+                self.write_operand(dest, Byte(result));
+            },
+            Rrc(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_rrc(value);
+                self.write_operand(dest, Byte(result));
+            },
+            Rl(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_rl(value);
+                self.write_operand(dest, Byte(result));
+            },
+            Rr(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_rr(value);
+                self.write_operand(dest, Byte(result));
+            },
+            Sla(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_sla(value);
+                self.write_operand(dest, Byte(result));
+            },
+            Sra(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_sra(value);
+                self.write_operand(dest, Byte(result));
+            },
+            Srl(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_srl(value);
+                self.write_operand(dest, Byte(result));
+            },
+            Swap(dest) => {
+                let value = self.read_byte_target(dest);
+                let result = self.alu_swap(value);
+                self.write_operand(dest, Byte(result));
+            },
+            Bit(bit, dest) => {
+                let value = self.read_byte_target(dest);
+                self.alu_bit(bit, value);
+            },
+            Res(bit, dest) => {
+                let value = self.read_byte_target(dest);
+                self.write_operand(dest, Byte(value & !(1 << bit)));
+            },
+            Set(bit, dest) => {
+                let value = self.read_byte_target(dest);
+                self.write_operand(dest, Byte(value | (1 << bit)));
+            },
+
+            Stop(_) => { // STOP is followed by a padding byte
+                self.inc_pc_by(1);
+                self.mmu.stop();
+            },
+            Halt => {
+                // with IME reset and an interrupt already pending HALT is skipped
+                // and the following byte is read twice (HALT bug)
+                match !self.ime && self.mmu.interrupts.pending() != 0 {
+                    true  => self.halt_bug = true,
+                    false => self.halted = true,
+                }
+            },
+            Nop => {},
+            Di => {
+                self.ime = false;
+                self.ei_delay = false;
+            },
+            Ei => self.ei_delay = true,
+        }
+        0
+    }
+
+    // any pending interrupt wakes the cpu up, even when it is not serviced
+    fn wake_up(&mut self) -> u32 {
+        match self.halted && self.mmu.interrupts.next_pending().is_some() {
+            true  => { self.halted = false; 4 },
+            false => 0,
+        }
+    }
+
+    // jumps to the highest priority pending interrupt handler, if any
+    fn handle_interrupts(&mut self) -> Option<u32> {
+
+        if !self.ime {
+            return None;
+        }
+        let interrupt = self.mmu.interrupts.next_pending()?;
+
+        self.ime = false;
+        self.mmu.interrupts.acknowledge(interrupt);
+        self.push_word(self.get_pc());
+        self.set_pc(interrupt.vector());
+
+        Some(20)
+    }
+
+    /// Executes exactly one instruction, returning the T-cycles it took
+    pub fn step(&mut self) -> u32 {
+
+        let cycles = match self.hung {
+            true  => 4, // not even interrupts get through, only the devices keep running
+            false => self.wake_up() + match self.handle_interrupts() {
+                Some(cycles)        => cycles,
+                None if self.halted => 4,
+                None                => self.exec_next(),
+            },
+        };
+        // keep the devices in lock-step with the cpu
+        self.mmu.tick(cycles);
+
+        // VRAM DMA halts the cpu while the devices keep running, which may start further blocks
+        let mut cycles = cycles;
+        let mut stall = self.mmu.take_stall();
+        while stall > 0 {
+            self.mmu.tick(stall);
+            cycles += stall;
+            stall = self.mmu.take_stall();
         }
+        cycles
+    }
+
+    fn exec_next(&mut self) -> u32 {
+
+        let enable_interrupts = self.ei_delay;
+
+        // fetch
+        let opcode = self.fetch_opcode();
+
+        // decode
+        let decoded = match opcode {
+
+                0xcb => {
+                    let prefixed = self.fetch_imm_byte();
+                    InstructionType::from_byte_prefixed(prefixed)
+                },
+                _ => InstructionType::from_byte(opcode),
+
+        };
+        // the unused opcodes lock the real hardware up
+        let Some((instruction, cycles)) = decoded else {
+            self.hung = true;
+            return 4;
+        };
+
+        // execute
+        let cycles = cycles as u32 + self.execute(instruction);
+
+        // EI takes effect once the instruction following it is done
+        if enable_interrupts && self.ei_delay {
+            self.ime = true;
+            self.ei_delay = false;
+        }
+        cycles
+    }
 
-        Ok(())
+    // hung, or halted with no enabled interrupt, there is nothing that can resume execution
+    pub fn is_locked(&self) -> bool {
+        self.hung || (self.halted && self.mmu.interrupts.enable & 0x1F == 0)
+    }
+
+    pub fn run(&mut self) {
+
+        while !self.is_locked() {
+            self.step();
+        }
     }
     pub fn reset(&mut self) {
         self.regs.pc = 0x100;
         self.halted = false;
+        self.hung = false;
+        self.ime = false;
+        self.ei_delay = false;
+        self.halt_bug = false;
     }
 
     #[cfg(test)]
     pub fn load_rom(&mut self, arr : Vec<u8>) {
-        
-        let mut addr : u16 = 0x100;
 
-        for opcode in arr.into_iter() {
-            self.set_byte(addr, opcode);
-            addr += 1;
-        }
+        // ROM is read-only from the bus, so the program is written to the cartridge itself
+        let rom = &mut self.mmu.cartridge.rom_data;
+        rom[0x100..0x100 + arr.len()].copy_from_slice(&arr);
         self.reset(); // resets cpu state
     }
 }
@@ -195,14 +712,23 @@ impl Cpu {
 #[cfg(test)]
 mod test {
     use crate::{
-        cpu::Cpu,
-        cartridge::CartContext,
+        cpu::{
+            Cpu,
+            regs::CpuFlag::{Z, N, H, C},
+        },
+        cartridge::{
+            CartContext,
+            header::CgbFlag,
+        },
+        memory::{
+            Memory,
+            interrupts::Interrupt,
+        },
     };
     #[test]
     fn exec_instr() {
 
-        let cart = CartContext::new();
-        let mut cpu = Cpu::new(&cart);
+        let mut cpu = Cpu::new(CartContext::new());
 
         cpu.load_rom(vec![
             0x3E, // LOAD A, n
@@ -222,7 +748,7 @@ mod test {
 
         cpu.load_rom(vec![
             0x21,       // LOAD HL, nn
-            0x64, 0x00, // wordconst 100
+            0x64, 0xC0, // wordconst 0xC064
             0x36,       // LD (HL), n
             0x30,       // byteconst 48
             0x7E,       // LD A, (HL)
@@ -230,22 +756,288 @@ mod test {
         ]);
         cpu.run();
 
-        assert_eq!(cpu.regs.hl(), 100);
+        assert_eq!(cpu.regs.hl(), 0xC064);
         assert_eq!(cpu.regs.a, 48);
 
         cpu.load_rom(vec![
             0x3E,       // LOAD A, n
             0x22,       // byteconst 34
             0xEA,       // LD (nn), A
-            0x64, 0x00, // wordconst 100
+            0x64, 0xC0, // wordconst 0xC064
             0x3E,       // LOAD A, n - maybe XOR A, A ?
             0x38,       // byteconst 56
             0xFA,       // LD A, (nn)
-            0x64, 0x00, // wordconst 100
+            0x64, 0xC0, // wordconst 0xC064
             0x76,       // HALT
         ]);
         cpu.run();
 
         assert_eq!(cpu.regs.a, 34);
     }
-}
\ No newline at end of file
+    #[test]
+    fn exec_arithmetic() {
+
+        let mut cpu = Cpu::new(CartContext::new());
+
+        cpu.load_rom(vec![
+            0x3E, 0x0F, // LD A, 0x0F
+            0xC6, 0x01, // ADD A, 1
+            0x76,       // HALT
+        ]);
+        cpu.run();
+
+        assert_eq!(cpu.regs.a, 0x10);
+        assert!(!cpu.regs.get_flag(Z));
+        assert!(!cpu.regs.get_flag(N));
+        assert!( cpu.regs.get_flag(H));
+        assert!(!cpu.regs.get_flag(C));
+
+        cpu.load_rom(vec![
+            0x3E, 0x10, // LD A, 0x10
+            0xD6, 0x20, // SUB 0x20
+            0x76,       // HALT
+        ]);
+        cpu.run();
+
+        assert_eq!(cpu.regs.a, 0xF0);
+        assert!( cpu.regs.get_flag(N));
+        assert!(!cpu.regs.get_flag(H));
+        assert!( cpu.regs.get_flag(C));
+
+        cpu.load_rom(vec![
+            0x3E, 0x15, // LD A, 0x15
+            0xC6, 0x27, // ADD A, 0x27
+            0x27,       // DAA
+            0x76,       // HALT
+        ]);
+        cpu.run();
+
+        assert_eq!(cpu.regs.a, 0x42); // BCD 15 + 27
+
+        cpu.load_rom(vec![
+            0x21, 0xFF, 0x0F, // LD HL, 0x0FFF
+            0x01, 0x01, 0x00, // LD BC, 1
+            0x09,             // ADD HL, BC
+            0x76,             // HALT
+        ]);
+        cpu.run();
+
+        assert_eq!(cpu.regs.hl(), 0x1000);
+        assert!( cpu.regs.get_flag(H));
+        assert!(!cpu.regs.get_flag(C));
+    }
+    #[test]
+    fn exec_prefixed() {
+
+        let mut cpu = Cpu::new(CartContext::new());
+
+        cpu.load_rom(vec![
+            0x3E, 0x81,       // LD A, 0x81
+            0xCB, 0x07,       // RLC A
+            0xCB, 0x37,       // SWAP A
+            0xCB, 0x7F,       // BIT 7, A
+            0x76,             // HALT
+        ]);
+        cpu.run();
+
+        assert_eq!(cpu.regs.a, 0x30);
+        assert!( cpu.regs.get_flag(Z));
+        assert!( cpu.regs.get_flag(H));
+        assert!(!cpu.regs.get_flag(C));
+
+        cpu.load_rom(vec![
+            0x21, 0x00, 0xC0, // LD HL, 0xC000
+            0x36, 0x0F,       // LD (HL), 0x0F
+            0xCB, 0xFE,       // SET 7, (HL)
+            0xCB, 0x86,       // RES 0, (HL)
+            0xCB, 0x2E,       // SRA (HL)
+            0x7E,             // LD A, (HL)
+            0x76,             // HALT
+        ]);
+        cpu.run();
+
+        assert_eq!(cpu.regs.a, 0xC7);
+        assert!(!cpu.regs.get_flag(C));
+    }
+    #[test]
+    fn step_cycles() {
+
+        let mut cpu = Cpu::new(CartContext::new());
+
+        cpu.load_rom(vec![
+            0xAF,             // XOR A
+            0x20, 0x10,       // JR NZ, 0x10 (not taken)
+            0x28, 0x00,       // JR Z, 0     (taken)
+            0xC3, 0x08, 0x01, // JP 0x0108
+            0xC4, 0x00, 0x00, // CALL NZ, 0  (not taken)
+            0xCC, 0x10, 0x01, // CALL Z, 0x0110 (taken)
+            0x00, 0x00,       // padding
+            0xC0,             // RET NZ      (not taken) <- 0x0110
+            0xC8,             // RET Z       (taken)
+        ]);
+        cpu.regs.sp = 0xFFFE;
+
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.step(), 8);
+        assert_eq!(cpu.step(), 12);
+        assert_eq!(cpu.step(), 16);
+        assert_eq!(cpu.step(), 12);
+        assert_eq!(cpu.step(), 24);
+        assert_eq!(cpu.step(), 8);
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.regs.pc, 0x010E);
+    }
+    #[test]
+    fn interrupts() {
+
+        let mut cpu = Cpu::new(CartContext::new());
+
+        cpu.load_rom(vec![
+            0xFB,             // EI
+            0x00,             // NOP
+            0x76,             // HALT
+        ]);
+        cpu.regs.sp = 0xFFFE;
+        cpu.mmu.interrupts.write_flags(0x00);
+        cpu.mmu.interrupts.enable = Interrupt::Timer as u8;
+
+        assert_eq!(cpu.step(), 4);  // EI
+        assert!(!cpu.ime);          // delayed by one instruction
+        assert_eq!(cpu.step(), 4);  // NOP
+        assert!(cpu.ime);
+        assert_eq!(cpu.step(), 4);  // HALT
+        assert!(cpu.halted);
+        assert_eq!(cpu.step(), 4);  // still halted
+
+        cpu.mmu.interrupts.request(Interrupt::Timer);
+        assert_eq!(cpu.step(), 24); // wake up and dispatch
+        assert!(!cpu.halted);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.regs.pc, Interrupt::Timer.vector());
+        assert_eq!(cpu.mmu.fetch_word(cpu.regs.sp), 0x0103);
+        assert_eq!(cpu.mmu.interrupts.pending(), 0);
+    }
+    #[test]
+    fn halt_bug() {
+
+        let mut cpu = Cpu::new(CartContext::new());
+
+        cpu.load_rom(vec![
+            0x76,             // HALT (IME = 0 with a pending interrupt)
+            0x3C,             // INC A (executed twice)
+            0x76,             // HALT
+        ]);
+        cpu.regs.a = 0;
+        cpu.mmu.interrupts.enable = Interrupt::VBlank as u8;
+        cpu.mmu.interrupts.request(Interrupt::VBlank);
+
+        cpu.step();
+        assert!(!cpu.halted);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.regs.a, 2);
+    }
+    #[test]
+    fn halt_wake_without_ime() {
+
+        let mut cpu = Cpu::new(CartContext::new());
+
+        cpu.load_rom(vec![
+            0x00,             // NOP
+            0x76,             // HALT
+            0x3C,             // INC A
+        ]);
+        cpu.regs.a = 0;
+        cpu.mmu.interrupts.write_flags(0x00);
+        cpu.mmu.interrupts.enable = Interrupt::Timer as u8;
+
+        cpu.step();
+        assert_eq!(cpu.step(), 4);  // HALT
+        assert_eq!(cpu.step(), 4);  // still halted
+
+        cpu.mmu.interrupts.request(Interrupt::Timer);
+        assert_eq!(cpu.step(), 4 + 4); // wake up, then INC A without servicing the interrupt
+        assert!(!cpu.halted);
+        assert_eq!(cpu.regs.a, 1);
+        assert_eq!(cpu.regs.pc, 0x0103);
+        assert_eq!(cpu.mmu.interrupts.pending(), Interrupt::Timer as u8);
+    }
+    #[test]
+    fn exec_control_flow() {
+
+        let mut cpu = Cpu::new(CartContext::new());
+
+        cpu.load_rom(vec![
+            0x31, 0xFE, 0xFF, // LD SP, 0xFFFE
+            0x06, 0x03,       // LD B, 3
+            0xAF,             // XOR A
+            0x3C,             // INC A      <- loop
+            0x05,             // DEC B
+            0x20, 0xFC,       // JR NZ, -4
+            0xCD, 0x11, 0x01, // CALL 0x0111
+            0x76,             // HALT
+            0x00, 0x00, 0x00, // padding
+            0xC5,             // PUSH BC    <- 0x0111
+            0xE1,             // POP HL
+            0xC9,             // RET
+        ]);
+        cpu.run();
+
+        assert_eq!(cpu.regs.a, 3);
+        assert_eq!(cpu.regs.b, 0);
+        assert_eq!(cpu.regs.hl(), cpu.regs.bc());
+        assert_eq!(cpu.regs.sp, 0xFFFE);
+        assert_eq!(cpu.regs.pc, 0x010E);
+    }
+    #[test]
+    fn cgb_mode() {
+
+        let mut cartridge = CartContext::new();
+        cartridge.header.cgb_flag = CgbFlag::Enhanced;
+        let mut cpu = Cpu::new(cartridge);
+        assert_eq!(cpu.regs.a, 0x11); // Game Boy Color detection
+
+        cpu.load_rom(vec![
+            0x3E, 0x01, // LD A, 1
+            0xE0, 0x4D, // LDH (KEY1), A
+            0x10, 0x00, // STOP
+            0x3E, 0x01, // LD A, 1
+            0xE0, 0x55, // LDH (HDMA5), A
+        ]);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.step(), 4);
+        assert!(cpu.mmu.double_speed());
+
+        cpu.step();
+        assert_eq!(cpu.step(), 12 + 2 * 64); // halted during the general purpose DMA
+    }
+    #[test]
+    fn unused_opcode() {
+
+        let mut cpu = Cpu::new(CartContext::new());
+
+        cpu.load_rom(vec![
+            0xFB, // EI
+            0x00, // NOP
+            0xD3, // unused
+            0x3C, // INC A
+        ]);
+        cpu.mmu.interrupts.write_flags(0x00);
+        cpu.mmu.interrupts.enable = 0x01;
+        cpu.step();
+        cpu.step();
+        assert!(!cpu.is_locked());
+
+        let a = cpu.regs.a;
+        assert_eq!(cpu.step(), 4);
+        assert!(cpu.hung);
+        assert!(cpu.is_locked());
+
+        cpu.mmu.interrupts.request(Interrupt::VBlank); // not serviced
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.regs.a, a);
+        assert_eq!(cpu.regs.pc, 0x0103);
+        cpu.run(); // returns right away
+    }
+}
@@ -20,6 +20,12 @@ pub struct Registers {
     pub sp  : u16  // stack pointer
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Registers {
     pub fn new() -> Registers {
         // initializing registers based on DMG CPU power-up sequence
@@ -37,6 +43,21 @@ impl Registers {
             sp : 0xFFFE,
         }
     }
+    pub fn new_cgb() -> Registers {
+        // CGB CPU power-up sequence, A = 0x11 lets games detect the Game Boy Color
+        Registers {
+            a  : 0x11,
+            b  : 0x00,
+            c  : 0x00,
+            d  : 0xFF,
+            e  : 0x56,
+            f  : 0x80, // set Z flag
+            h  : 0x00,
+            l  : 0x0D,
+            pc : 0x0100,
+            sp : 0xFFFE,
+        }
+    }
     fn get_wide_reg(&self, high : u8, low : u8) -> u16 {
         ((high as u16) << 8) | (low as u16)
     }
@@ -102,13 +123,12 @@ mod test {
         let flags = [Z, N, H, C];
 
         // check if it can safely set and reset the cpu flags
-        for i in 0..4 {
-            let mask = flags[i];
-            assert_eq!(regs.get_flag(mask), false);
+        for mask in flags {
+            assert!(!regs.get_flag(mask));
             regs.set_flag(mask, true);
-            assert_eq!(regs.get_flag(mask), true);
+            assert!(regs.get_flag(mask));
             regs.set_flag(mask, false);
-            assert_eq!(regs.get_flag(mask), false);
+            assert!(!regs.get_flag(mask));
         }
     }
 }
\ No newline at end of file
@@ -1,26 +1,137 @@
 pub mod cartridge;
 pub mod memory;
 pub mod cpu;
+pub mod ppu;
+pub mod apu;
 
 pub mod emu {
     
     use std::env;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
 
     use super::{
-        cartridge::CartContext,
-        cpu::Cpu,
+        apu::Apu,
+        cartridge::{
+            CartContext,
+            checksum,
+            error::Validation,
+            mbc3::RtcClock,
+        },
+        cpu::{Cpu, CPU_CLOCK},
     };
+    pub use super::memory::joypad::Button;
+
+    // battery saves are flushed every 5 emulated seconds, so a crash loses little progress
+    const SAVE_INTERVAL : u32 = 5 * CPU_CLOCK;
+
+    pub struct Emulator {
+        pub cpu     : Cpu,
+        save_cycles : u32,             // cycles since the last battery save flush
+        quit        : Arc<AtomicBool>, // set from outside, e.g. by a Ctrl-C handler, to stop `run`
+    }
 
-    pub fn run() -> Result<(), ()> {
+    impl Emulator {
+        pub fn new(cartridge : CartContext) -> Self {
+            Emulator {
+                cpu         : Cpu::new(cartridge),
+                save_cycles : 0,
+                quit        : Arc::new(AtomicBool::new(false)),
+            }
+        }
+        /// Flag that makes `run` return once set, safe to set from another thread or a signal handler
+        pub fn quit_handle(&self) -> Arc<AtomicBool> {
+            Arc::clone(&self.quit)
+        }
+        /// Executes one instruction, returning the T-cycles it took
+        pub fn step(&mut self) -> u32 {
+            let cycles = self.cpu.step();
+
+            self.save_cycles += cycles;
+            if self.save_cycles >= SAVE_INTERVAL {
+                self.save_cycles = 0;
+                if let Err(err) = self.flush_save() {
+                    eprintln!("Failed to write save file: {}", err);
+                }
+            }
+            cycles
+        }
+        /// Runs until the cpu locks up or the quit flag is set, then flushes the battery save
+        pub fn run(&mut self) -> std::io::Result<()> {
+            while !self.cpu.is_locked() && !self.quit.load(Ordering::Relaxed) {
+                self.step();
+            }
+            self.flush_save()
+        }
+        /// Writes battery backed RAM next to the ROM if it changed
+        pub fn flush_save(&mut self) -> std::io::Result<()> {
+            self.cpu.mmu.cartridge.flush_save()
+        }
+        /// Presses or releases a button, as driven by a frontend or an input movie
+        pub fn set_button(&mut self, button : Button, pressed : bool) {
+            let mmu = &mut self.cpu.mmu;
+            mmu.joypad.set_button(button, pressed, &mut mmu.interrupts);
+        }
+        /// Turns sound emulation on, producing samples at the host sample rate
+        pub fn enable_audio(&mut self, sample_rate : u32) {
+            self.cpu.mmu.apu = Some(Apu::new(sample_rate));
+        }
+        /// Drains the interleaved stereo samples produced so far, empty with audio disabled
+        pub fn take_samples(&mut self) -> Vec<f32> {
+            self.cpu.mmu.apu.as_mut().map_or_else(Vec::new, Apu::take_samples)
+        }
+        /// Whether the cartridge rumble motor is currently on
+        pub fn rumble(&self) -> bool {
+            self.cpu.mmu.cartridge.rumble()
+        }
+    }
+
+    // the last battery save also goes out when the emulator is dropped, whatever ends the session
+    impl Drop for Emulator {
+        fn drop(&mut self) {
+            // a failure has nowhere to go from here, `run` and `flush_save` report theirs
+            let _ = self.flush_save();
+        }
+    }
+
+    pub fn run() -> Result<(), String> {
 
         let file_path : String = env::args().nth(1)
                                     .expect("Expected path to the ROM file");
 
+        // fix-header <rom>: rewrites the logo and checksums, for homebrew builds
+        if file_path == "fix-header" {
+            let rom_path = env::args().nth(2).expect("Expected path to the ROM file");
+            return checksum::fix_header_file(&rom_path)
+                .map_err(|err| format!("Failed to fix ROM file {}: {}", rom_path, err));
+        }
+
+        // --lenient boots images with a bad header, like homebrew without checksums
+        let validation = match env::args().skip(2).any(|arg| arg == "--lenient") {
+            true  => Validation::Lenient,
+            false => Validation::Strict,
+        };
+
         let mut ctx = CartContext::new();
 
-        ctx.load(&file_path).expect(&format!("Failed to load ROM file: {}", file_path));
+        ctx.load(&file_path, validation)
+            .map_err(|err| format!("Failed to load ROM file {}: {}", file_path, err))?;
+        ctx.print_info();
+        for warning in &ctx.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        ctx.set_rtc_clock(RtcClock::WallClock); // keeps time across sessions
+
+        let mut emulator = Emulator::new(ctx);
+
+        // Ctrl-C stops the emulation loop so the battery save is flushed before exiting
+        let quit = emulator.quit_handle();
+        ctrlc::set_handler(move || quit.store(true, Ordering::Relaxed))
+            .map_err(|err| format!("Failed to install the Ctrl-C handler: {}", err))?;
 
-        Ok(())
+        emulator.run().map_err(|err| format!("Failed to write save file: {}", err))
     }
 }
 
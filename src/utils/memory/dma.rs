@@ -0,0 +1,75 @@
+// 0xFF46: DMA - OAM DMA source address / start
+// copies 0xXX00 - 0xXX9F to 0xFE00 - 0xFE9F, one byte per M-cycle (640 T-cycles in total)
+// meanwhile the cpu can only reach HRAM and restart the transfer through 0xFF46,
+// everything else reads 0xFF and ignores writes
+// https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+
+pub const OAM_DMA_LENGTH : u16 = 0xA0;
+
+#[derive(Debug, Default)]
+pub struct OamDma {
+    register : u8,
+    source   : u16,
+    index    : u16,  // next byte to copy
+    active   : bool,
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        OamDma::default()
+    }
+
+    pub fn active(&self) -> bool { self.active }
+
+    pub fn read(&self) -> u8 { self.register }
+    // starts a transfer, restarting any transfer in progress
+    pub fn write(&mut self, value : u8) {
+        self.register = value;
+        // sources past WRAM read its echo
+        self.source = match value {
+            0xE0..=0xFF => (value as u16 - 0x20) << 8,
+            _           => (value as u16) << 8,
+        };
+        self.index = 0;
+        self.active = true;
+    }
+
+    /// Source and OAM destination of the byte copied during the next M-cycle
+    pub fn next_byte(&mut self) -> Option<(u16, u16)> {
+        if !self.active {
+            return None;
+        }
+        let byte = (self.source | self.index, 0xFE00 | self.index);
+
+        self.index += 1;
+        if self.index == OAM_DMA_LENGTH {
+            self.active = false;
+        }
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OamDma;
+
+    #[test]
+    fn transfer() {
+        let mut dma = OamDma::new();
+        assert_eq!(dma.next_byte(), None);
+
+        dma.write(0xC1);
+        assert_eq!(dma.read(), 0xC1);
+        assert_eq!(dma.next_byte(), Some((0xC100, 0xFE00)));
+        assert_eq!(dma.next_byte(), Some((0xC101, 0xFE01)));
+
+        dma.write(0xF0); // restarts from the echo of 0xD000
+        assert_eq!(dma.read(), 0xF0);
+        for index in 0..0xA0 {
+            assert!(dma.active());
+            assert_eq!(dma.next_byte(), Some((0xD000 + index, 0xFE00 + index)));
+        }
+        assert!(!dma.active());
+        assert_eq!(dma.next_byte(), None);
+    }
+}
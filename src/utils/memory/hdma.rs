@@ -0,0 +1,122 @@
+// 0xFF51: HDMA1 - Source, high byte
+// 0xFF52: HDMA2 - Source, low byte (bits 0-3 ignored)
+// 0xFF53: HDMA3 - VRAM destination, high byte (bits 5-7 ignored)
+// 0xFF54: HDMA4 - VRAM destination, low byte (bits 0-3 ignored)
+// 0xFF55: HDMA5 - Length / mode / start
+// bit 7  : 0 = general purpose DMA, 1 = H-Blank DMA
+// bit 0-6: number of 0x10 byte blocks minus one
+// https://gbdev.io/pandocs/CGB_Registers.html#lcd-vram-dma-transfers
+
+pub const BLOCK_SIZE : u16 = 0x10;
+
+// T-cycles the cpu is halted for each block, doubled in double speed mode
+pub const BLOCK_CYCLES : u32 = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Transfer {
+    General, // copies everything at once
+    HBlank,  // copies a block at the start of each H-Blank
+}
+
+#[derive(Debug, Default)]
+pub struct Hdma {
+    source    : u16,
+    dest      : u16,  // offset within VRAM
+    remaining : u8,   // blocks left to copy
+    hblank    : bool, // H-Blank transfer in progress
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma::default()
+    }
+
+    /// Whether a block is due at the next H-Blank
+    pub fn hblank_active(&self) -> bool { self.hblank }
+    /// Whether blocks are left to copy, even from a cancelled transfer
+    pub fn pending(&self) -> bool { self.remaining > 0 }
+
+    pub fn read(&self, addr : u16) -> u8 {
+        match addr {
+            // bit 7 is clear while an H-Blank transfer is running, 0xFF once everything was copied
+            0xFF55 => ((!self.hblank as u8) << 7) | (self.remaining.wrapping_sub(1) & 0x7F),
+            _ => 0xFF, // write only
+        }
+    }
+    // returns the transfer started by a write to HDMA5
+    pub fn write(&mut self, addr : u16, value : u8) -> Option<Transfer> {
+        match addr {
+            0xFF51 => self.source = (self.source & 0x00FF) | ((value as u16) << 8),
+            0xFF52 => self.source = (self.source & 0xFF00) | (value & 0xF0) as u16,
+            0xFF53 => self.dest = (self.dest & 0x00FF) | (((value & 0x1F) as u16) << 8),
+            0xFF54 => self.dest = (self.dest & 0xFF00) | (value & 0xF0) as u16,
+            0xFF55 => {
+                // clearing bit 7 during an H-Blank transfer cancels it
+                if self.hblank && value & 0x80 == 0 {
+                    self.hblank = false;
+                    return None;
+                }
+                self.remaining = (value & 0x7F) + 1;
+                self.hblank = value & 0x80 != 0;
+                return Some(if self.hblank { Transfer::HBlank } else { Transfer::General });
+            },
+            _ => panic!("Invalid HDMA address {:#06X}", addr)
+        }
+        None
+    }
+
+    /// Source and VRAM destination of the next block, moving both past it
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let block = (self.source, 0x8000 | self.dest);
+
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        self.dest = (self.dest + BLOCK_SIZE) & 0x1FF0;
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.hblank = false;
+        }
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Hdma,
+        Transfer,
+    };
+
+    #[test]
+    fn registers() {
+        let mut hdma = Hdma::new();
+        assert_eq!(hdma.read(0xFF55), 0xFF);
+
+        hdma.write(0xFF51, 0xC1);
+        hdma.write(0xFF52, 0x2F); // low nibble ignored
+        hdma.write(0xFF53, 0xFF); // within VRAM
+        hdma.write(0xFF54, 0xE5);
+        assert_eq!(hdma.read(0xFF51), 0xFF);
+
+        assert_eq!(hdma.write(0xFF55, 0x81), Some(Transfer::HBlank));
+        assert_eq!(hdma.read(0xFF55), 0x01);
+        assert_eq!(hdma.next_block(), Some((0xC120, 0x9FE0)));
+        assert_eq!(hdma.read(0xFF55), 0x00);
+        assert_eq!(hdma.next_block(), Some((0xC130, 0x9FF0)));
+        assert!(!hdma.hblank_active());
+        assert_eq!(hdma.read(0xFF55), 0xFF);
+        assert_eq!(hdma.next_block(), None);
+
+        assert_eq!(hdma.write(0xFF55, 0x82), Some(Transfer::HBlank));
+        assert_eq!(hdma.next_block(), Some((0xC140, 0x8000))); // destination wraps within VRAM
+        assert_eq!(hdma.write(0xFF55, 0x00), None); // cancelled
+        assert!(!hdma.hblank_active());
+        assert_eq!(hdma.read(0xFF55), 0x81);
+
+        assert_eq!(hdma.write(0xFF55, 0x00), Some(Transfer::General));
+        assert_eq!(hdma.next_block(), Some((0xC150, 0x8010)));
+        assert_eq!(hdma.next_block(), None);
+    }
+}
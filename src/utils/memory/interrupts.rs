@@ -0,0 +1,66 @@
+// 0xFF0F: Interrupt Flag (IF) - requested interrupts
+// 0xFFFF: Interrupt Enable (IE) - enabled interrupts
+// https://gbdev.io/pandocs/Interrupts.html
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interrupt { // ordered by priority
+    VBlank = 0b0000_0001,
+    Stat   = 0b0000_0010, // LCD status
+    Timer  = 0b0000_0100,
+    Serial = 0b0000_1000,
+    Joypad = 0b0001_0000,
+}
+
+impl Interrupt {
+    // address of the interrupt handler
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::Stat   => 0x48,
+            Interrupt::Timer  => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+        }
+    }
+}
+
+const INTERRUPTS : [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::Stat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad,
+];
+
+#[derive(Debug, Default)]
+pub struct Interrupts {
+    pub enable : u8, // IE
+        flags  : u8, // IF (only the lower 5 bits are used)
+}
+
+impl Interrupts {
+    pub fn new() -> Self {
+        Interrupts {
+            enable : 0x00,
+            flags  : 0x01, // VBlank is already requested after boot
+        }
+    }
+    pub fn request(&mut self, interrupt : Interrupt) {
+        self.flags |= interrupt as u8;
+    }
+    pub fn acknowledge(&mut self, interrupt : Interrupt) {
+        self.flags &= !(interrupt as u8);
+    }
+    // interrupts that are both requested and enabled
+    pub fn pending(&self) -> u8 {
+        self.enable & self.flags & 0x1F
+    }
+    // highest priority interrupt among the pending ones
+    pub fn next_pending(&self) -> Option<Interrupt> {
+        let pending = self.pending();
+        INTERRUPTS.into_iter().find(|&interrupt| pending & (interrupt as u8) != 0)
+    }
+
+    pub fn read_flags(&self) -> u8 { self.flags | 0xE0 } // upper bits always read as 1
+    pub fn write_flags(&mut self, value : u8) { self.flags = value & 0x1F; }
+}
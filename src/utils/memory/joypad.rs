@@ -0,0 +1,129 @@
+use super::interrupts::{
+    Interrupts,
+    Interrupt,
+};
+
+// 0xFF00: P1/JOYP - Joypad
+// bit 5: select action buttons    (0 = selected)
+// bit 4: select direction buttons (0 = selected)
+// bit 3: Down  or Start  (0 = pressed)
+// bit 2: Up    or Select (0 = pressed)
+// bit 1: Left  or B      (0 = pressed)
+// bit 0: Right or A      (0 = pressed)
+// https://gbdev.io/pandocs/Joypad_Input.html
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Button {
+    Right, Left, Up, Down, // direction buttons
+    A, B, Select, Start,   // action buttons
+}
+
+impl Button {
+    // bit of the button within its group
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right | Button::A      => 0b0001,
+            Button::Left  | Button::B      => 0b0010,
+            Button::Up    | Button::Select => 0b0100,
+            Button::Down  | Button::Start  => 0b1000,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Joypad {
+    select     : u8, // bits 4-5 as written by the cpu
+    directions : u8, // pressed direction buttons (1 = pressed)
+    actions    : u8, // pressed action buttons (1 = pressed)
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            select     : 0x30, // no group selected
+            directions : 0x00,
+            actions    : 0x00,
+        }
+    }
+
+    // pressed buttons of the selected groups, active high
+    fn lines(&self) -> u8 {
+        let mut lines = 0x00;
+        if self.select & 0x10 == 0 { lines |= self.directions; }
+        if self.select & 0x20 == 0 { lines |= self.actions; }
+        lines
+    }
+    // the interrupt is requested when any input line goes from high to low
+    fn update(&mut self, previous : u8, interrupts : &mut Interrupts) {
+        if !previous & self.lines() != 0 {
+            interrupts.request(Interrupt::Joypad);
+        }
+    }
+
+    pub fn set_button(&mut self, button : Button, pressed : bool, interrupts : &mut Interrupts) {
+        let previous = self.lines();
+        let group = match button {
+            Button::Right | Button::Left | Button::Up | Button::Down => &mut self.directions,
+            _ => &mut self.actions,
+        };
+        match pressed {
+            true  => *group |=  button.mask(),
+            false => *group &= !button.mask(),
+        }
+        self.update(previous, interrupts);
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | (!self.lines() & 0x0F)
+    }
+    pub fn write(&mut self, value : u8, interrupts : &mut Interrupts) {
+        let previous = self.lines();
+        self.select = value & 0x30; // only the selection bits are writable
+        self.update(previous, interrupts);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Joypad,
+        Button,
+        Interrupts,
+    };
+
+    #[test]
+    fn select_groups() {
+        let mut joypad = Joypad::new();
+        let mut interrupts = Interrupts::new();
+
+        joypad.set_button(Button::Start, true, &mut interrupts);
+        joypad.set_button(Button::Left, true, &mut interrupts);
+        assert_eq!(joypad.read(), 0xFF);
+
+        joypad.write(0x20, &mut interrupts); // directions
+        assert_eq!(joypad.read(), 0xED);
+        joypad.write(0x10, &mut interrupts); // actions
+        assert_eq!(joypad.read(), 0xD7);
+
+        joypad.set_button(Button::Start, false, &mut interrupts);
+        assert_eq!(joypad.read(), 0xDF);
+    }
+    #[test]
+    fn interrupt() {
+        let mut joypad = Joypad::new();
+        let mut interrupts = Interrupts::new();
+        interrupts.write_flags(0x00);
+        interrupts.enable = 0xFF;
+
+        joypad.set_button(Button::A, true, &mut interrupts); // not selected
+        assert_eq!(interrupts.pending(), 0);
+
+        joypad.write(0x10, &mut interrupts); // selecting it pulls the line low
+        assert_eq!(interrupts.pending(), 0x10);
+
+        interrupts.write_flags(0x00);
+        joypad.set_button(Button::A, false, &mut interrupts);
+        joypad.set_button(Button::B, true, &mut interrupts);
+        assert_eq!(interrupts.pending(), 0x10);
+    }
+}
@@ -1,6 +1,28 @@
 pub mod timer;
+pub mod interrupts;
+pub mod joypad;
+pub mod hdma;
+pub mod dma;
 
-use super::cartridge::CartContext;
+use super::{
+    cartridge::{
+        CartContext,
+        header::CgbFlag,
+    },
+    ppu::{
+        Ppu,
+        Mode,
+    },
+    apu::Apu,
+};
+use interrupts::Interrupts;
+use timer::Timer;
+use joypad::Joypad;
+use hdma::{
+    Hdma,
+    Transfer,
+};
+use dma::OamDma;
 
 pub trait Memory {
     fn fetch_byte(&self, addr : u16) -> u8;
@@ -8,11 +30,11 @@ pub trait Memory {
     fn set_byte(&mut self, addr : u16, value : u8);
 
     fn fetch_word(&self, addr : u16) -> u16 {
-        u16::from(self.fetch_byte(addr)) | (u16::from(self.fetch_byte(addr + 1)) << 8)
+        u16::from(self.fetch_byte(addr)) | (u16::from(self.fetch_byte(addr.wrapping_add(1))) << 8)
     }
      fn set_word(&mut self, addr : u16, value : u16) {
         self.set_byte(addr, (value & 0xFF) as u8);
-        self.set_byte(addr + 1, (value >> 8) as u8)
+        self.set_byte(addr.wrapping_add(1), (value >> 8) as u8)
     }
 }
 
@@ -23,9 +45,9 @@ pub trait Memory {
 // 0xC000 - 0xCFFF: Work RAM (WRAM)
 // 0xD000 - 0xDFFF: Work RAM (WRAM)
 // 0xE000 - 0xFDFF: Mirror of 0xC000-0xDDFF (ECHO RAM)
-// 0xFE00 - 0xFEF9: Sprite Attribute table (OAM)
+// 0xFE00 - 0xFE9F: Sprite Attribute table (OAM)
 // 0xFEA0 - 0xFEFF: Not Usable
-// 0xFF00 - 0xFF7F: I/O Registers
+// 0xFF00 - 0xFF7F: I/O Registers (KEY1 0xFF4D, HDMA 0xFF51 - 0xFF55 and SVBK 0xFF70 in CGB mode)
 // 0xFF80 - 0xFFFE: High RAM (HRAM)
 // 0xFFFF - 0xFFFF: Interrupt ENable Register (IE)
 
@@ -33,45 +55,370 @@ const HRAM_SIZE : usize = 0x7F;
 const WRAM_SIZE : usize = 0x8000;
 
 pub struct Mmu {
-    // pub apu : Option<Apu>,
-    // pub gpu : Gpu,
-    // pub joypad : Joypad,
-    cartridge : CartContext,
-    hram      : [u8; HRAM_SIZE],
-    wram      : [u8; WRAM_SIZE],
-    wram_bank : usize,
+    pub interrupts : Interrupts,
+    pub timer      : Timer,
+    pub joypad     : Joypad,
+    pub hdma       : Hdma,
+    pub oam_dma    : OamDma,
+    pub ppu        : Ppu,
+    pub apu        : Option<Apu>, // audio is skipped when not attached
+    pub cartridge  : CartContext,
+    pub cgb        : bool, // Game Boy Color hardware, selected by the cartridge header
+    hram           : [u8; HRAM_SIZE],
+    wram           : [u8; WRAM_SIZE],
+    wram_bank      : usize,
+    double_speed   : bool,
+    speed_armed    : bool, // KEY1 bit 0, the speed switches on the next STOP
+    stall          : u32,  // T-cycles the cpu owes to VRAM DMA
 }
 
 impl Mmu {
-    pub fn new(cartridge : &CartContext) -> Self {
+    pub fn new(cartridge : CartContext) -> Self {
+        let cgb = cartridge.header.cgb_flag != CgbFlag::Dmg;
         Mmu {
-            cartridge : CartContext::new(), // todo!(update to set from args)
-            hram      : [0u8; HRAM_SIZE],
-            wram      : [0u8; WRAM_SIZE],
-            wram_bank : 0,
+            interrupts : Interrupts::new(),
+            timer      : Timer::new(),
+            joypad     : Joypad::new(),
+            hdma       : Hdma::new(),
+            oam_dma    : OamDma::new(),
+            ppu        : if cgb { Ppu::new_cgb() } else { Ppu::new() },
+            apu        : None,
+            cartridge,
+            cgb,
+            hram       : [0u8; HRAM_SIZE],
+            wram       : [0u8; WRAM_SIZE],
+            wram_bank  : 1, // 0xD000 - 0xDFFF is fixed to bank 1 on the DMG
+            double_speed : false,
+            speed_armed  : false,
+            stall        : 0,
         }
     }
+    pub fn double_speed(&self) -> bool { self.double_speed }
+
+    // executes STOP, which switches the CGB speed when armed through KEY1
+    pub fn stop(&mut self) {
+        self.timer.write(0xFF04, 0); // STOP resets the divider
+        if self.cgb && self.speed_armed {
+            self.double_speed = !self.double_speed;
+            self.speed_armed = false;
+        }
+    }
+    /// Returns the T-cycles the cpu is halted for by VRAM DMA since the previous call
+    pub fn take_stall(&mut self) -> u32 { std::mem::take(&mut self.stall) }
+
+    // copies the next VRAM DMA block, halting the cpu meanwhile
+    fn hdma_block(&mut self) {
+        if let Some((source, dest)) = self.hdma.next_block() {
+            for offset in 0..hdma::BLOCK_SIZE {
+                let value = self.read(source.wrapping_add(offset));
+                self.ppu.write_vram(dest + offset, value);
+            }
+            self.stall += hdma::BLOCK_CYCLES << self.double_speed as u32;
+        }
+    }
+    fn write_hdma(&mut self, addr : u16, value : u8) {
+        match self.hdma.write(addr, value) {
+            Some(Transfer::General) => while self.hdma.pending() { self.hdma_block() },
+            // started during H-Blank, the first block doesn't wait for the next one
+            Some(Transfer::HBlank) if self.ppu.mode() == Mode::HBlank => self.hdma_block(),
+            _ => {}
+        }
+    }
+    // advances the memory mapped devices by the given T-cycles
+    pub fn tick(&mut self, cycles : u32) {
+        self.timer.tick(cycles, &mut self.interrupts);
+
+        // OAM DMA copies a byte every M-cycle, so the PPU sees the objects arrive one by one
+        for _ in 0..cycles / 4 {
+            if let Some((source, dest)) = self.oam_dma.next_byte() {
+                let value = self.read(source);
+                self.ppu.write_oam(dest, value);
+            }
+        }
+
+        // in double speed mode the timer keeps up with the cpu, everything else runs at the normal rate
+        let cycles = if self.double_speed { cycles / 2 } else { cycles };
+        self.ppu.tick(cycles, &mut self.interrupts);
+        if self.ppu.take_hblank() && self.hdma.hblank_active() {
+            self.hdma_block();
+        }
+        if let Some(apu) = self.apu.as_mut() {
+            apu.tick(cycles);
+        }
+        self.cartridge.tick(cycles);
+    }
 }
 
-impl Memory for Mmu {
+// the bus as seen by the DMA units, which ignore their own conflicts
+impl Mmu {
 
-    fn fetch_byte(&self, addr : u16) -> u8 {
+    fn read(&self, addr : u16) -> u8 {
 
         match addr {
           0x0000..=0x7FFF  => self.cartridge.read(addr),
-          0xC000..=0xDFFF  => self.wram[(addr & 0x1FFF) as usize],
+          0x8000..=0x9FFF  => self.ppu.read_vram(addr),
+          0xA000..=0xBFFF  => self.cartridge.read_ram(addr),
+          0xC000..=0xCFFF  => self.wram[(addr & 0x0FFF) as usize],
+          0xD000..=0xDFFF  => self.wram[self.wram_bank * 0x1000 + (addr & 0x0FFF) as usize],
+          0xE000..=0xFDFF  => self.read(addr - 0x2000), // echo RAM
+          0xFE00..=0xFE9F  => self.ppu.read_oam(addr),
+          0xFF00           => self.joypad.read(),
+          0xFF04..=0xFF07  => self.timer.read(addr),
+          0xFF0F           => self.interrupts.read_flags(),
+          0xFF10..=0xFF3F  => self.apu.as_ref().map_or(0xFF, |apu| apu.read(addr)),
+          0xFF40..=0xFF45 |
+          0xFF47..=0xFF4B  => self.ppu.read(addr),
+          0xFF46           => self.oam_dma.read(),
+          0xFF4D if self.cgb => 0x7E | ((self.double_speed as u8) << 7) | self.speed_armed as u8,
+          0xFF4F |
+          0xFF68..=0xFF6B  => self.ppu.read(addr),
+          0xFF51..=0xFF55 if self.cgb => self.hdma.read(addr),
+          0xFF70 if self.cgb => 0xF8 | self.wram_bank as u8,
           0xFF80..=0xFFFE  => self.hram[(addr - 0xFF80) as usize],
-          _ => panic!("Invalid Address")
+          0xFFFF           => self.interrupts.enable,
+          _ => 0xFF // not usable and unmapped I/O registers
         }
     }
 
-    fn set_byte(&mut self, addr : u16, value : u8) {
+    fn write(&mut self, addr : u16, value : u8) {
 
         match addr {
             0x0000..=0x7FFF  => self.cartridge.write(addr, value),
-            0xC000..=0xDFFF  => self.wram[(addr & 0x1FFF) as usize] = value,
+            0x8000..=0x9FFF  => self.ppu.write_vram(addr, value),
+            0xA000..=0xBFFF  => self.cartridge.write_ram(addr, value),
+            0xC000..=0xCFFF  => self.wram[(addr & 0x0FFF) as usize] = value,
+            0xD000..=0xDFFF  => self.wram[self.wram_bank * 0x1000 + (addr & 0x0FFF) as usize] = value,
+            0xE000..=0xFDFF  => self.write(addr - 0x2000, value), // echo RAM
+            0xFE00..=0xFE9F  => self.ppu.write_oam(addr, value),
+            0xFF00           => self.joypad.write(value, &mut self.interrupts),
+            0xFF04..=0xFF07  => self.timer.write(addr, value),
+            0xFF0F           => self.interrupts.write_flags(value),
+            0xFF10..=0xFF3F  => if let Some(apu) = self.apu.as_mut() { apu.write(addr, value) },
+            0xFF40..=0xFF45 |
+            0xFF47..=0xFF4B  => self.ppu.write(addr, value),
+            0xFF46           => self.oam_dma.write(value),
+            0xFF4D if self.cgb => self.speed_armed = value & 0x01 != 0,
+            0xFF4F |
+            0xFF68..=0xFF6B  => self.ppu.write(addr, value),
+            0xFF51..=0xFF55 if self.cgb => self.write_hdma(addr, value),
+            0xFF70 if self.cgb => self.wram_bank = ((value & 0x07) as usize).max(1), // bank 0 selects bank 1
             0xFF80..=0xFFFE  => self.hram[(addr - 0xFF80) as usize] = value,
-            _ => panic!("Invalid Address")
+            0xFFFF           => self.interrupts.enable = value,
+            _ => {} // not usable and unmapped I/O registers
         };
     }
+
+    // during OAM DMA the cpu only reaches HRAM, and 0xFF46 to restart the transfer
+    fn dma_conflict(&self, addr : u16) -> bool {
+        self.oam_dma.active() && !(0xFF80..=0xFFFE).contains(&addr)
+    }
+}
+
+impl Memory for Mmu {
+
+    fn fetch_byte(&self, addr : u16) -> u8 {
+        match self.dma_conflict(addr) {
+            true  => 0xFF,
+            false => self.read(addr),
+        }
+    }
+
+    fn set_byte(&mut self, addr : u16, value : u8) {
+        if addr == 0xFF46 || !self.dma_conflict(addr) {
+            self.write(addr, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Mmu,
+        Memory,
+    };
+    use crate::cartridge::{
+        CartContext,
+        header::CgbFlag,
+    };
+
+    fn cgb() -> Mmu {
+        let mut cartridge = CartContext::new();
+        cartridge.header.cgb_flag = CgbFlag::Only;
+        Mmu::new(cartridge)
+    }
+
+    #[test]
+    fn echo_ram() {
+        let mut mmu = Mmu::new(CartContext::new());
+
+        mmu.set_byte(0xC123, 0x42);
+        assert_eq!(mmu.fetch_byte(0xE123), 0x42);
+        mmu.set_byte(0xFDFF, 0x24);
+        assert_eq!(mmu.fetch_byte(0xDDFF), 0x24);
+    }
+    #[test]
+    fn unmapped() {
+        let mut mmu = Mmu::new(CartContext::new());
+
+        mmu.set_byte(0xFEA0, 0x12);
+        assert_eq!(mmu.fetch_byte(0xFEA0), 0xFF);
+        assert_eq!(mmu.fetch_byte(0xFF7F), 0xFF);
+        assert_eq!(mmu.fetch_word(0xFFFF) >> 8, mmu.fetch_byte(0x0000) as u16);
+
+        // no address can bring the emulator down
+        for addr in 0x0000..=0xFFFF {
+            let value = mmu.fetch_byte(addr);
+            mmu.set_byte(addr, value);
+        }
+    }
+    #[test]
+    fn cgb_banking() {
+        let mut mmu = Mmu::new(CartContext::new());
+        mmu.set_byte(0xFF70, 0x02);
+        mmu.set_byte(0xD000, 0x11);
+        assert_eq!(mmu.fetch_byte(0xFF70), 0xFF);
+        assert_eq!(mmu.fetch_byte(0xFF4F), 0xFF);
+
+        let mut mmu = cgb();
+        mmu.set_byte(0xD000, 0x11);
+        mmu.set_byte(0xFF70, 0x07);
+        assert_eq!(mmu.fetch_byte(0xFF70), 0xFF);
+        assert_eq!(mmu.fetch_byte(0xD000), 0x00);
+        mmu.set_byte(0xD000, 0x77);
+        mmu.set_byte(0xFF70, 0x00); // bank 0 selects bank 1
+        assert_eq!(mmu.fetch_byte(0xFF70), 0xF9);
+        assert_eq!(mmu.fetch_byte(0xF000), 0x11); // echo RAM follows the bank
+        mmu.set_byte(0xC000, 0x42); // bank 0 is fixed
+        mmu.set_byte(0xFF70, 0x07);
+        assert_eq!(mmu.fetch_byte(0xD000), 0x77);
+        assert_eq!(mmu.fetch_byte(0xC000), 0x42);
+
+        mmu.set_byte(0x8000, 0x01);
+        mmu.set_byte(0xFF4F, 0x01);
+        assert_eq!(mmu.fetch_byte(0x8000), 0x00);
+        mmu.set_byte(0x8000, 0x02);
+        mmu.set_byte(0xFF4F, 0x00);
+        assert_eq!(mmu.fetch_byte(0x8000), 0x01);
+    }
+    #[test]
+    fn double_speed() {
+        let mut mmu = Mmu::new(CartContext::new());
+        mmu.set_byte(0xFF4D, 0x01);
+        mmu.stop();
+        assert!(!mmu.double_speed());
+        assert_eq!(mmu.fetch_byte(0xFF4D), 0xFF);
+
+        let mut mmu = cgb();
+        assert_eq!(mmu.fetch_byte(0xFF4D), 0x7E);
+        mmu.stop(); // not armed
+        assert!(!mmu.double_speed());
+        mmu.set_byte(0xFF4D, 0x01);
+        assert_eq!(mmu.fetch_byte(0xFF4D), 0x7F);
+        mmu.tick(4 * 64);
+        mmu.stop();
+        assert!(mmu.double_speed());
+        assert_eq!(mmu.fetch_byte(0xFF4D), 0xFE);
+        assert_eq!(mmu.fetch_byte(0xFF04), 0x00); // the divider is reset
+
+        // the timer runs at the cpu rate, the PPU at half of it
+        mmu.ppu.write(0xFF40, 0x00);
+        mmu.ppu.write(0xFF40, 0x91);
+        mmu.tick(456);
+        assert_eq!(mmu.fetch_byte(0xFF04), 0x01);
+        assert_eq!(mmu.fetch_byte(0xFF44), 0x00);
+        mmu.tick(456);
+        assert_eq!(mmu.fetch_byte(0xFF44), 0x01);
+    }
+    #[test]
+    fn vram_dma() {
+        let mut mmu = cgb();
+        for offset in 0..0x40 {
+            mmu.set_byte(0xC000 + offset, offset as u8);
+        }
+        mmu.set_byte(0xFF51, 0xC0);
+        mmu.set_byte(0xFF52, 0x00);
+        mmu.set_byte(0xFF53, 0x10);
+        mmu.set_byte(0xFF54, 0x00);
+
+        // general purpose: everything at once, halting the cpu
+        mmu.set_byte(0xFF55, 0x01);
+        assert_eq!(mmu.fetch_byte(0x901F), 0x1F);
+        assert_eq!(mmu.fetch_byte(0x9020), 0x00);
+        assert_eq!(mmu.fetch_byte(0xFF55), 0xFF);
+        assert_eq!(mmu.take_stall(), 2 * 32);
+
+        // H-Blank: one block per visible line
+        mmu.ppu.write(0xFF40, 0x00);
+        mmu.ppu.write(0xFF40, 0x91);
+        mmu.set_byte(0xFF55, 0x81);
+        assert_eq!(mmu.fetch_byte(0xFF55), 0x01);
+        assert_eq!(mmu.take_stall(), 0);
+        mmu.tick(252);
+        assert_eq!(mmu.fetch_byte(0x902F), 0x2F);
+        assert_eq!(mmu.fetch_byte(0x9030), 0x00);
+        assert_eq!(mmu.fetch_byte(0xFF55), 0x00);
+        assert_eq!(mmu.take_stall(), 32);
+        mmu.tick(456);
+        assert_eq!(mmu.fetch_byte(0x903F), 0x3F);
+        assert_eq!(mmu.fetch_byte(0xFF55), 0xFF);
+
+        // started during H-Blank, the first block is copied right away
+        mmu.take_stall();
+        mmu.set_byte(0xFF55, 0x82);
+        assert_eq!(mmu.fetch_byte(0xFF55), 0x01);
+        assert_eq!(mmu.take_stall(), 32);
+
+        // cancelled before the next H-Blank
+        mmu.tick(204);
+        mmu.set_byte(0xFF55, 0x00);
+        assert_eq!(mmu.fetch_byte(0xFF55), 0x81);
+        mmu.tick(456);
+        assert_eq!(mmu.take_stall(), 0);
+        assert_eq!(mmu.fetch_byte(0xFF55), 0x81);
+
+        let mut mmu = Mmu::new(CartContext::new());
+        mmu.set_byte(0xFF55, 0x00);
+        assert_eq!(mmu.fetch_byte(0xFF55), 0xFF);
+        assert_eq!(mmu.take_stall(), 0);
+    }
+    #[test]
+    fn oam_dma() {
+        let mut mmu = Mmu::new(CartContext::new());
+        for offset in 0..0xA0 {
+            mmu.set_byte(0xC100 + offset, offset as u8 + 1);
+        }
+        mmu.set_byte(0xC000, 0x55);
+        mmu.set_byte(0xFF80, 0x42);
+
+        mmu.set_byte(0xFF46, 0xC1);
+        assert_eq!(mmu.fetch_byte(0xC100), 0xFF); // bus conflict
+        assert_eq!(mmu.fetch_byte(0x0000), 0xFF);
+        assert_eq!(mmu.fetch_byte(0xFF80), 0x42); // HRAM is still reachable
+        assert_eq!(mmu.fetch_byte(0xFF44), 0xFF); // I/O registers are not
+        assert_eq!(mmu.fetch_byte(0xFFFF), 0xFF);
+        mmu.set_byte(0xC100, 0x00);               // ignored
+        mmu.set_byte(0xFF47, 0x00);
+
+        mmu.tick(8);
+        assert_eq!(mmu.ppu.read_oam(0xFE01), 0x02);
+        assert_eq!(mmu.ppu.read_oam(0xFE02), 0x00); // not copied yet
+
+        mmu.tick(640 - 8 - 4);
+        assert_eq!(mmu.fetch_byte(0xFE00), 0xFF);
+        mmu.tick(4);
+        assert_eq!(mmu.fetch_byte(0xFE9F), 0xA0);
+        assert_eq!(mmu.fetch_byte(0xC100), 0x01);
+        assert_eq!(mmu.fetch_byte(0xFF47), 0xFC);
+        assert_eq!(mmu.fetch_byte(0xFF46), 0xC1);
+
+        mmu.set_byte(0xFF46, 0xC1);
+        mmu.tick(8);
+        mmu.set_byte(0xFF46, 0xC0); // restarts from 0xC000
+        mmu.tick(640 - 4);
+        assert_eq!(mmu.fetch_byte(0xFE00), 0xFF); // still copying
+        mmu.tick(4);
+        assert_eq!(mmu.fetch_byte(0xFE00), 0x55);
+        assert_eq!(mmu.fetch_byte(0xFE9F), 0x00);
+        assert_eq!(mmu.fetch_byte(0xFF46), 0xC0);
+    }
 }
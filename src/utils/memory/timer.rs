@@ -1,6 +1,148 @@
-#![allow(dead_code, unused)]
+use super::interrupts::{
+    Interrupts,
+    Interrupt,
+};
 
-trait Timer {
-    fn new() -> Self;
-    fn tick(&mut self); 
-}
\ No newline at end of file
+// 0xFF04: DIV  - Divider register (upper 8 bits of the internal counter)
+// 0xFF05: TIMA - Timer counter
+// 0xFF06: TMA  - Timer modulo
+// 0xFF07: TAC  - Timer control
+// https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+
+#[derive(Debug, Default)]
+pub struct Timer {
+    divider  : u16,  // internal 16-bit counter, incremented every T-cycle
+    tima     : u8,
+    tma      : u8,
+    tac      : u8,
+    overflow : bool, // TIMA overflowed, reload is delayed by one M-cycle
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            divider  : 0xABCC, // DMG value after the boot ROM
+            tima     : 0x00,
+            tma      : 0x00,
+            tac      : 0x00,
+            overflow : false,
+        }
+    }
+
+    // TIMA is incremented on the falling edge of the divider bit selected by TAC
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0x00 => 9, // 4096 Hz
+            0x01 => 3, // 262144 Hz
+            0x02 => 5, // 65536 Hz
+            _    => 7, // 16384 Hz
+        };
+        (self.tac & 0x04 != 0) && (self.divider >> bit) & 0x01 != 0
+    }
+    fn inc_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflow = overflow;
+    }
+    // updates the divider, incrementing TIMA on a falling edge of the selected bit
+    fn set_divider(&mut self, value : u16) {
+        let old_signal = self.signal();
+        self.divider = value;
+        if old_signal && !self.signal() {
+            self.inc_tima();
+        }
+    }
+
+    pub fn tick(&mut self, cycles : u32, interrupts : &mut Interrupts) {
+        // the timer is clocked once per M-cycle
+        for _ in 0..(cycles / 4) {
+            if self.overflow { // reload TIMA after the delay
+                self.overflow = false;
+                self.tima = self.tma;
+                interrupts.request(Interrupt::Timer);
+            }
+            self.set_divider(self.divider.wrapping_add(4));
+        }
+    }
+
+    pub fn read(&self, addr : u16) -> u8 {
+        match addr {
+            0xFF04 => (self.divider >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8, // unused bits read as 1
+            _ => panic!("Invalid timer address {:#06X}", addr)
+        }
+    }
+    pub fn write(&mut self, addr : u16, value : u8) {
+        match addr {
+            0xFF04 => self.set_divider(0), // any write resets the divider
+            0xFF05 => {                    // writing during the delay cancels the reload
+                self.tima = value;
+                self.overflow = false;
+            },
+            0xFF06 => self.tma = value,
+            0xFF07 => {                    // disabling or switching the bit can also cause an increment
+                let old_signal = self.signal();
+                self.tac = value & 0x07;
+                if old_signal && !self.signal() {
+                    self.inc_tima();
+                }
+            },
+            _ => panic!("Invalid timer address {:#06X}", addr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Timer,
+        Interrupts,
+    };
+
+    #[test]
+    fn divider() {
+        let mut timer = Timer::new();
+        let mut interrupts = Interrupts::new();
+
+        timer.write(0xFF04, 0x12);
+        assert_eq!(timer.read(0xFF04), 0x00);
+
+        timer.tick(256, &mut interrupts);
+        assert_eq!(timer.read(0xFF04), 0x01);
+    }
+    #[test]
+    fn tima_overflow() {
+        let mut timer = Timer::new();
+        let mut interrupts = Interrupts::new();
+        interrupts.write_flags(0x00);
+        interrupts.enable = 0xFF;
+
+        timer.write(0xFF04, 0x00);
+        timer.write(0xFF06, 0xAB);
+        timer.write(0xFF05, 0xFF);
+        timer.write(0xFF07, 0x05); // enabled, 16 T-cycles per increment
+
+        timer.tick(16, &mut interrupts);
+        assert_eq!(timer.read(0xFF05), 0x00); // overflowed, reload is pending
+        assert_eq!(interrupts.pending(), 0);
+
+        timer.tick(4, &mut interrupts);
+        assert_eq!(timer.read(0xFF05), 0xAB);
+        assert_eq!(interrupts.pending(), 0x04);
+    }
+    #[test]
+    fn falling_edge() {
+        let mut timer = Timer::new();
+        let mut interrupts = Interrupts::new();
+
+        timer.write(0xFF04, 0x00);
+        timer.write(0xFF05, 0x00);
+        timer.write(0xFF07, 0x05);
+
+        timer.tick(8, &mut interrupts); // bit 3 of the divider is now set
+        timer.write(0xFF04, 0x00);      // resetting it is a falling edge
+        assert_eq!(timer.read(0xFF05), 0x01);
+    }
+}
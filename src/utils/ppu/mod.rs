@@ -0,0 +1,629 @@
+use super::memory::interrupts::{
+    Interrupts,
+    Interrupt,
+};
+
+// 0x8000 - 0x97FF: Tile data
+// 0x9800 - 0x9BFF: Tile map 0
+// 0x9C00 - 0x9FFF: Tile map 1
+// 0xFE00 - 0xFE9F: Object attribute memory (OAM)
+// 0xFF40 - 0xFF4B: LCD registers
+// 0xFF4F         : VRAM bank (CGB)
+// 0xFF68 - 0xFF6B: Color palette RAM (CGB)
+// https://gbdev.io/pandocs/Rendering.html
+
+pub const SCREEN_WIDTH  : usize = 160;
+pub const SCREEN_HEIGHT : usize = 144;
+
+const VRAM_BANK_SIZE   : usize = 0x2000;
+const OAM_SIZE         : usize = 0xA0;
+const PALETTE_RAM_SIZE : usize = 0x40; // 8 palettes of 4 RGB555 colors
+
+const DOTS_PER_LINE    : u32   = 456;
+const OAM_SCAN_DOTS    : u32   = 80;
+const DRAWING_DOTS     : u32   = 172;
+const VBLANK_LINE      : u8    = 144;
+const LINES_PER_FRAME  : u8    = 154;
+const SPRITES_PER_LINE : usize = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Mode {
+    HBlank  = 0,
+    VBlank  = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+// LCD control register (LCDC) bits
+#[derive(Copy, Clone)]
+enum Lcdc {
+    BgEnable     = 0b0000_0001,
+    ObjEnable    = 0b0000_0010,
+    ObjSize      = 0b0000_0100, // 8x16 objects
+    BgMap        = 0b0000_1000, // 0x9C00 tile map for the background
+    TileData     = 0b0001_0000, // 0x8000 unsigned addressing
+    WindowEnable = 0b0010_0000,
+    WindowMap    = 0b0100_0000, // 0x9C00 tile map for the window
+    LcdEnable    = 0b1000_0000,
+}
+
+// DMG shades as RGB555 grays
+const DMG_COLORS : [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+// a background or window pixel along with its CGB attributes
+#[derive(Default, Copy, Clone)]
+struct BgPixel {
+    color    : u8,   // color index (0-3)
+    palette  : u8,   // CGB palette number (0-7)
+    priority : bool, // CGB: drawn over objects
+}
+
+pub struct Ppu {
+    cgb       : bool,
+    vram      : [u8; VRAM_BANK_SIZE * 2], // the second bank is only reachable in CGB mode
+    vram_bank : usize,
+    oam       : [u8; OAM_SIZE],
+
+    lcdc : u8,
+    stat : u8, // only the interrupt source selection bits (3-6) are stored
+    scy  : u8,
+    scx  : u8,
+    ly   : u8,
+    lyc  : u8,
+    bgp  : u8,
+    obp0 : u8,
+    obp1 : u8,
+    wy   : u8,
+    wx   : u8,
+
+    // CGB palette index registers: bit 7 auto-increments on data writes
+    bcps         : u8,
+    ocps         : u8,
+    bg_palettes  : [u8; PALETTE_RAM_SIZE],
+    obj_palettes : [u8; PALETTE_RAM_SIZE],
+
+    mode        : Mode,
+    dots        : u32,  // dots elapsed on the current line
+    window_line : u8,   // internal line counter of the window
+    stat_line   : bool, // STAT interrupt is requested on its rising edge
+
+    framebuffer     : Vec<u8>,  // shade indices (0 = white, 3 = black), color indices in CGB mode
+    rgb_framebuffer : Vec<u16>, // RGB555 colors
+    frame_ready     : bool,
+    hblank_started  : bool, // a visible line entered H-Blank, for H-Blank DMA
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        // register values after the DMG boot ROM
+        Ppu {
+            cgb       : false,
+            vram      : [0u8; VRAM_BANK_SIZE * 2],
+            vram_bank : 0,
+            oam       : [0u8; OAM_SIZE],
+            lcdc : 0x91,
+            stat : 0x00,
+            scy  : 0x00,
+            scx  : 0x00,
+            ly   : 0x00,
+            lyc  : 0x00,
+            bgp  : 0xFC,
+            obp0 : 0xFF,
+            obp1 : 0xFF,
+            wy   : 0x00,
+            wx   : 0x00,
+            bcps         : 0x00,
+            ocps         : 0x00,
+            bg_palettes  : [0xFF; PALETTE_RAM_SIZE], // white
+            obj_palettes : [0xFF; PALETTE_RAM_SIZE],
+            mode        : Mode::OamScan,
+            dots        : 0,
+            window_line : 0,
+            stat_line   : false,
+            framebuffer     : vec![0u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+            rgb_framebuffer : vec![DMG_COLORS[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready     : false,
+            hblank_started  : false,
+        }
+    }
+    /// PPU of a Game Boy Color running a CGB cartridge
+    pub fn new_cgb() -> Self {
+        Ppu {
+            cgb : true,
+            ..Self::new()
+        }
+    }
+
+    fn lcdc_flag(&self, flag : Lcdc) -> bool { self.lcdc & (flag as u8) != 0 }
+
+    pub fn mode(&self) -> Mode { self.mode }
+
+    /// Returns the last completed frame if a new one was finished since the previous call
+    pub fn take_frame(&mut self) -> Option<&[u8]> {
+        match std::mem::take(&mut self.frame_ready) {
+            true  => Some(&self.framebuffer),
+            false => None,
+        }
+    }
+    /// Returns whether a visible line entered H-Blank since the previous call
+    pub fn take_hblank(&mut self) -> bool { std::mem::take(&mut self.hblank_started) }
+    /// Last rendered frame as 160x144 shade indices, row by row
+    pub fn framebuffer(&self) -> &[u8] { &self.framebuffer }
+    /// Last rendered frame as 160x144 RGB555 colors, row by row
+    pub fn framebuffer_rgb555(&self) -> &[u16] { &self.rgb_framebuffer }
+
+    pub fn read_vram(&self, addr : u16) -> u8 {
+        self.vram[self.vram_bank * VRAM_BANK_SIZE + (addr & 0x1FFF) as usize]
+    }
+    pub fn write_vram(&mut self, addr : u16, value : u8) {
+        self.vram[self.vram_bank * VRAM_BANK_SIZE + (addr & 0x1FFF) as usize] = value;
+    }
+
+    pub fn read_oam(&self, addr : u16) -> u8 { self.oam[(addr - 0xFE00) as usize] }
+    pub fn write_oam(&mut self, addr : u16, value : u8) { self.oam[(addr - 0xFE00) as usize] = value; }
+
+    pub fn read(&self, addr : u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => 0x80 | self.stat | (((self.ly == self.lyc) as u8) << 2) | self.mode as u8,
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            // CGB registers read as open bus on the DMG
+            0xFF4F if self.cgb => 0xFE | self.vram_bank as u8,
+            0xFF68 if self.cgb => 0x40 | self.bcps,
+            0xFF69 if self.cgb => self.bg_palettes[(self.bcps & 0x3F) as usize],
+            0xFF6A if self.cgb => 0x40 | self.ocps,
+            0xFF6B if self.cgb => self.obj_palettes[(self.ocps & 0x3F) as usize],
+            0xFF4F | 0xFF68..=0xFF6B => 0xFF,
+            _ => panic!("Invalid PPU address {:#06X}", addr)
+        }
+    }
+    pub fn write(&mut self, addr : u16, value : u8) {
+        match addr {
+            0xFF40 => {
+                let was_enabled = self.lcdc_flag(Lcdc::LcdEnable);
+                self.lcdc = value;
+                match (was_enabled, self.lcdc_flag(Lcdc::LcdEnable)) {
+                    (true, false) => { // LY is held at 0 while the LCD is off
+                        self.ly = 0;
+                        self.dots = 0;
+                        self.mode = Mode::HBlank;
+                    },
+                    (false, true) => {
+                        self.dots = 0;
+                        self.window_line = 0;
+                        self.mode = Mode::OamScan;
+                    },
+                    _ => {}
+                }
+            },
+            0xFF41 => self.stat = value & 0x78,
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            0xFF44 => {}, // read only
+            0xFF45 => self.lyc = value,
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            0xFF4F if self.cgb => self.vram_bank = (value & 0x01) as usize,
+            0xFF68 if self.cgb => self.bcps = value & 0xBF,
+            0xFF69 if self.cgb => write_palette(&mut self.bg_palettes, &mut self.bcps, value),
+            0xFF6A if self.cgb => self.ocps = value & 0xBF,
+            0xFF6B if self.cgb => write_palette(&mut self.obj_palettes, &mut self.ocps, value),
+            0xFF4F | 0xFF68..=0xFF6B => {},
+            _ => panic!("Invalid PPU address {:#06X}", addr)
+        }
+    }
+
+    pub fn tick(&mut self, cycles : u32, interrupts : &mut Interrupts) {
+        if !self.lcdc_flag(Lcdc::LcdEnable) {
+            return;
+        }
+        for _ in 0..cycles {
+            self.dot(interrupts);
+        }
+    }
+
+    fn dot(&mut self, interrupts : &mut Interrupts) {
+        self.dots += 1;
+
+        match self.mode {
+            Mode::OamScan if self.dots == OAM_SCAN_DOTS => {
+                self.mode = Mode::Drawing;
+            },
+            Mode::Drawing if self.dots == OAM_SCAN_DOTS + DRAWING_DOTS => {
+                self.render_scanline();
+                self.mode = Mode::HBlank;
+                self.hblank_started = true;
+            },
+            Mode::HBlank | Mode::VBlank if self.dots == DOTS_PER_LINE => {
+                self.dots = 0;
+                self.ly += 1;
+
+                if self.ly == VBLANK_LINE {
+                    self.mode = Mode::VBlank;
+                    self.window_line = 0;
+                    self.frame_ready = true;
+                    interrupts.request(Interrupt::VBlank);
+                } else if self.ly == LINES_PER_FRAME {
+                    self.ly = 0;
+                    self.mode = Mode::OamScan;
+                } else if self.ly < VBLANK_LINE {
+                    self.mode = Mode::OamScan;
+                }
+            },
+            _ => {}
+        }
+        self.update_stat(interrupts);
+    }
+
+    fn update_stat(&mut self, interrupts : &mut Interrupts) {
+        let line = (self.stat & 0x40 != 0 && self.ly == self.lyc)
+            || (self.stat & 0x20 != 0 && self.mode == Mode::OamScan)
+            || (self.stat & 0x10 != 0 && self.mode == Mode::VBlank)
+            || (self.stat & 0x08 != 0 && self.mode == Mode::HBlank);
+
+        if line && !self.stat_line {
+            interrupts.request(Interrupt::Stat);
+        }
+        self.stat_line = line;
+    }
+
+    // color index (0-3) of a pixel of the given tile
+    fn tile_pixel(&self, tile_addr : usize, x : u8, y : u8) -> u8 {
+        let lo = self.vram[tile_addr + (y as usize) * 2];
+        let hi = self.vram[tile_addr + (y as usize) * 2 + 1];
+        let bit = 7 - x;
+        (((hi >> bit) & 0x01) << 1) | ((lo >> bit) & 0x01)
+    }
+    // background or window pixel, CGB attributes are stored in the tile map of bank 1
+    fn map_pixel(&self, map_addr : usize, x : u8, y : u8) -> BgPixel {
+        let offset = map_addr + (y as usize / 8) * 32 + (x as usize / 8);
+        let tile = self.vram[offset];
+        let attr = if self.cgb { self.vram[VRAM_BANK_SIZE + offset] } else { 0 };
+
+        let mut tile_addr = match self.lcdc_flag(Lcdc::TileData) {
+            true  => (tile as usize) * 16,                        // 0x8000 + unsigned index
+            false => (0x1000 + (tile as i8 as i32) * 16) as usize // 0x9000 + signed index
+        };
+        if attr & 0x08 != 0 {
+            tile_addr += VRAM_BANK_SIZE;
+        }
+        let col = if attr & 0x20 != 0 { 7 - x % 8 } else { x % 8 }; // horizontal flip
+        let row = if attr & 0x40 != 0 { 7 - y % 8 } else { y % 8 }; // vertical flip
+
+        BgPixel {
+            color    : self.tile_pixel(tile_addr, col, row),
+            palette  : attr & 0x07,
+            priority : attr & 0x80 != 0,
+        }
+    }
+
+    fn render_scanline(&mut self) {
+        let ly = self.ly;
+        let mut bg_pixels = [BgPixel::default(); SCREEN_WIDTH];
+
+        // on the DMG the bit 0 of LCDC blanks both background and window to white,
+        // in CGB mode it only takes the priority away from them
+        let bg_enabled = self.cgb || self.lcdc_flag(Lcdc::BgEnable);
+        if bg_enabled {
+            let bg_map = if self.lcdc_flag(Lcdc::BgMap) { 0x1C00 } else { 0x1800 };
+            let y = self.scy.wrapping_add(ly);
+
+            for (x, pixel) in bg_pixels.iter_mut().enumerate() {
+                *pixel = self.map_pixel(bg_map, self.scx.wrapping_add(x as u8), y);
+            }
+
+            if self.lcdc_flag(Lcdc::WindowEnable) && self.wy <= ly && self.wx <= 166 {
+                let window_map = if self.lcdc_flag(Lcdc::WindowMap) { 0x1C00 } else { 0x1800 };
+                let start = self.wx as i32 - 7;
+
+                for (x, pixel) in bg_pixels.iter_mut().enumerate().skip(start.max(0) as usize) {
+                    *pixel = self.map_pixel(window_map, (x as i32 - start) as u8, self.window_line);
+                }
+                self.window_line += 1;
+            }
+        }
+
+        let line = ly as usize * SCREEN_WIDTH;
+        for (x, pixel) in bg_pixels.iter().enumerate() {
+            let (index, rgb) = match (self.cgb, bg_enabled) {
+                (true, _)      => (pixel.color, palette_color(&self.bg_palettes, pixel.palette, pixel.color)),
+                (false, true)  => dmg_color(self.bgp, pixel.color),
+                (false, false) => (0, DMG_COLORS[0]), // bypasses BGP
+            };
+            self.framebuffer[line + x] = index;
+            self.rgb_framebuffer[line + x] = rgb;
+        }
+
+        if self.lcdc_flag(Lcdc::ObjEnable) {
+            self.render_sprites(&bg_pixels);
+        }
+    }
+
+    fn render_sprites(&mut self, bg_pixels : &[BgPixel; SCREEN_WIDTH]) {
+        let ly = self.ly as i32;
+        let height = if self.lcdc_flag(Lcdc::ObjSize) { 16 } else { 8 };
+
+        // OAM scan: the first 10 objects overlapping the line, in OAM order
+        let mut sprites : Vec<(usize, &[u8])> = self.oam.chunks(4)
+            .enumerate()
+            .filter(|(_, sprite)| {
+                let top = sprite[0] as i32 - 16;
+                ly >= top && ly < top + height
+            })
+            .take(SPRITES_PER_LINE)
+            .collect();
+
+        // on the DMG the smaller X coordinate wins, then the OAM index, in CGB mode only the OAM index
+        if !self.cgb {
+            sprites.sort_by_key(|&(index, sprite)| (sprite[1], index));
+        }
+        // in CGB mode the bit 0 of LCDC clear puts objects above everything
+        let bg_master = !self.cgb || self.lcdc_flag(Lcdc::BgEnable);
+
+        let mut drawn = [false; SCREEN_WIDTH];
+        let line = self.ly as usize * SCREEN_WIDTH;
+
+        for (_, sprite) in sprites {
+            let (y, x, tile, attr) = (sprite[0] as i32, sprite[1] as i32, sprite[2], sprite[3]);
+
+            let mut row = ly - (y - 16);
+            if attr & 0x40 != 0 { // vertical flip
+                row = height - 1 - row;
+            }
+            let tile = match height {
+                16 => tile & 0xFE,
+                _  => tile,
+            } as usize;
+            let mut tile_addr = (tile + (row as usize / 8)) * 16;
+            if self.cgb && attr & 0x08 != 0 {
+                tile_addr += VRAM_BANK_SIZE;
+            }
+            let palette = if attr & 0x10 != 0 { self.obp1 } else { self.obp0 };
+
+            for px in 0..8 {
+                let screen_x = x - 8 + px;
+                if !(0..SCREEN_WIDTH as i32).contains(&screen_x) || drawn[screen_x as usize] {
+                    continue;
+                }
+                let col = if attr & 0x20 != 0 { 7 - px } else { px }; // horizontal flip
+                let color = self.tile_pixel(tile_addr, col as u8, (row % 8) as u8);
+                if color == 0 { // transparent
+                    continue;
+                }
+                // lower priority objects are hidden even when this one is behind the background
+                drawn[screen_x as usize] = true;
+                let bg = bg_pixels[screen_x as usize];
+                if bg_master && (attr & 0x80 != 0 || bg.priority) && bg.color != 0 {
+                    continue;
+                }
+                let (index, rgb) = match self.cgb {
+                    true  => (color, palette_color(&self.obj_palettes, attr & 0x07, color)),
+                    false => dmg_color(palette, color),
+                };
+                self.framebuffer[line + screen_x as usize] = index;
+                self.rgb_framebuffer[line + screen_x as usize] = rgb;
+            }
+        }
+    }
+}
+
+// maps a color index to a shade through a DMG palette register
+fn apply_palette(palette : u8, color : u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+// shade and RGB555 gray of a color index through a DMG palette register
+fn dmg_color(palette : u8, color : u8) -> (u8, u16) {
+    let shade = apply_palette(palette, color);
+    (shade, DMG_COLORS[shade as usize])
+}
+
+// RGB555 color of a CGB palette, stored little endian
+fn palette_color(palettes : &[u8; PALETTE_RAM_SIZE], palette : u8, color : u8) -> u16 {
+    let index = (palette as usize) * 8 + (color as usize) * 2;
+    u16::from_le_bytes([palettes[index], palettes[index + 1]]) & 0x7FFF
+}
+
+// writes palette RAM through BCPD/OCPD, moving the index along when auto-increment is set
+fn write_palette(palettes : &mut [u8; PALETTE_RAM_SIZE], spec : &mut u8, value : u8) {
+    palettes[(*spec & 0x3F) as usize] = value;
+    if *spec & 0x80 != 0 {
+        *spec = 0x80 | (spec.wrapping_add(1) & 0x3F);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Ppu,
+        Mode,
+        Interrupts,
+        SCREEN_WIDTH,
+    };
+
+    fn setup() -> (Ppu, Interrupts) {
+        let mut ppu = Ppu::new();
+        let mut interrupts = Interrupts::new();
+        interrupts.write_flags(0x00);
+        interrupts.enable = 0xFF;
+        ppu.write(0xFF40, 0x00); // restart the LCD from line 0
+        ppu.write(0xFF40, 0x93);
+        (ppu, interrupts)
+    }
+
+    #[test]
+    fn mode_timing() {
+        let (mut ppu, mut interrupts) = setup();
+
+        assert_eq!(ppu.mode(), Mode::OamScan);
+        ppu.tick(80, &mut interrupts);
+        assert_eq!(ppu.mode(), Mode::Drawing);
+        ppu.tick(172, &mut interrupts);
+        assert_eq!(ppu.mode(), Mode::HBlank);
+        ppu.tick(204, &mut interrupts);
+        assert_eq!(ppu.read(0xFF44), 1);
+
+        ppu.tick(456 * 143, &mut interrupts);
+        assert_eq!(ppu.read(0xFF44), 144);
+        assert_eq!(ppu.mode(), Mode::VBlank);
+        assert_eq!(interrupts.pending(), 0x01);
+        assert!(ppu.take_frame().is_some());
+        assert!(ppu.take_frame().is_none());
+
+        ppu.tick(456 * 10, &mut interrupts);
+        assert_eq!(ppu.read(0xFF44), 0);
+        assert_eq!(ppu.mode(), Mode::OamScan);
+    }
+    #[test]
+    fn lyc_interrupt() {
+        let (mut ppu, mut interrupts) = setup();
+
+        ppu.write(0xFF45, 2);
+        ppu.write(0xFF41, 0x40);
+        ppu.tick(456, &mut interrupts);
+        assert_eq!(interrupts.pending(), 0);
+        ppu.tick(456, &mut interrupts);
+        assert_eq!(interrupts.pending(), 0x02);
+        assert_eq!(ppu.read(0xFF41) & 0x04, 0x04);
+    }
+    #[test]
+    fn render_background_and_sprite() {
+        let (mut ppu, mut interrupts) = setup();
+
+        ppu.write(0xFF47, 0xE4); // identity palette
+        ppu.write(0xFF48, 0xE4);
+        for row in 0..8 { // tile 1: color 3 on every pixel
+            ppu.write_vram(0x8010 + row * 2, 0xFF);
+            ppu.write_vram(0x8011 + row * 2, 0xFF);
+        }
+        for row in 0..8 { // tile 2: color 1 on the leftmost pixel
+            ppu.write_vram(0x8020 + row * 2, 0x80);
+        }
+        ppu.write_vram(0x9801, 0x01); // second background tile
+
+        ppu.write_oam(0xFE00, 16);    // Y
+        ppu.write_oam(0xFE01, 8 + 2); // X
+        ppu.write_oam(0xFE02, 0x02);  // tile
+
+        ppu.tick(456, &mut interrupts);
+
+        let frame = ppu.framebuffer();
+        assert_eq!(&frame[0..SCREEN_WIDTH][6..10], &[0, 0, 3, 3]);
+        assert_eq!(frame[2], 1);
+        assert_eq!(frame[3], 0);
+        assert_eq!(ppu.framebuffer_rgb555()[8], 0x0000); // black
+    }
+    #[test]
+    fn background_disabled() {
+        let (mut ppu, mut interrupts) = setup();
+
+        ppu.write(0xFF47, 0xFF); // every color is black
+        for row in 0..16 { // tile 0: color 3 on every pixel
+            ppu.write_vram(0x8000 + row, 0xFF);
+        }
+        ppu.write(0xFF40, 0x92); // background off
+
+        ppu.tick(456, &mut interrupts);
+
+        assert!(ppu.framebuffer()[0..SCREEN_WIDTH].iter().all(|&shade| shade == 0));
+        assert!(ppu.framebuffer_rgb555()[0..SCREEN_WIDTH].iter().all(|&rgb| rgb == 0x7FFF));
+    }
+    #[test]
+    fn cgb_palettes() {
+        let mut ppu = Ppu::new();
+        ppu.write(0xFF68, 0x80);
+        ppu.write(0xFF69, 0x12);
+        assert_eq!(ppu.read(0xFF69), 0xFF); // no palette RAM on the DMG
+
+        let mut ppu = Ppu::new_cgb();
+        ppu.write(0xFF68, 0x80 | 0x3F); // auto-increment from the last byte
+        ppu.write(0xFF69, 0x12);
+        assert_eq!(ppu.read(0xFF68), 0xC0); // wrapped around to 0
+        ppu.write(0xFF69, 0x34);
+        ppu.write(0xFF68, 0x3F);
+        assert_eq!(ppu.read(0xFF69), 0x12);
+        ppu.write(0xFF69, 0x56); // no auto-increment
+        assert_eq!(ppu.read(0xFF68), 0x7F);
+        ppu.write(0xFF68, 0x00);
+        assert_eq!(ppu.read(0xFF69), 0x34);
+    }
+    #[test]
+    fn render_cgb() {
+        let mut ppu = Ppu::new_cgb();
+        let mut interrupts = Interrupts::new();
+        ppu.write(0xFF40, 0x00);
+        ppu.write(0xFF40, 0x93);
+
+        // BG palette 0 color 1: green, BG palette 2 color 1: red, OBJ palette 1 color 3: blue
+        ppu.write(0xFF68, 0x80 | 2);
+        ppu.write(0xFF69, 0xE0);
+        ppu.write(0xFF69, 0x03);
+        ppu.write(0xFF68, 0x80 | (2 * 8 + 2));
+        ppu.write(0xFF69, 0x1F);
+        ppu.write(0xFF69, 0x00);
+        ppu.write(0xFF6A, 0x80 | (8 + 6));
+        ppu.write(0xFF6B, 0x00);
+        ppu.write(0xFF6B, 0x7C);
+
+        for row in 0..8 { // bank 0 tile 1: color 1 on every pixel
+            ppu.write_vram(0x8010 + row * 2, 0xFF);
+        }
+        ppu.write_vram(0x9800, 0x01);
+        ppu.write_vram(0x9801, 0x01);
+
+        ppu.write(0xFF4F, 0x01);
+        assert_eq!(ppu.read(0xFF4F), 0xFF);
+        for row in 0..8 { // bank 1 tile 1: color 1 on the leftmost pixel
+            ppu.write_vram(0x8010 + row * 2, 0x80);
+        }
+        for row in 0..8 { // bank 1 tile 2: color 3 on every pixel
+            ppu.write_vram(0x8020 + row * 2, 0xFF);
+            ppu.write_vram(0x8021 + row * 2, 0xFF);
+        }
+        ppu.write_vram(0x9800, 0x08 | 0x20 | 0x02); // bank 1, horizontal flip, palette 2
+        ppu.write_vram(0x9801, 0x80);               // priority over objects
+        ppu.write(0xFF4F, 0x00);
+        assert_eq!(ppu.read(0xFF4F), 0xFE);
+
+        // two objects from bank 1 with palette 1, over the priority tile and over tile 0
+        for (index, x) in [16, 24].into_iter().enumerate() {
+            let addr = 0xFE00 + index as u16 * 4;
+            ppu.write_oam(addr, 16);
+            ppu.write_oam(addr + 1, x);
+            ppu.write_oam(addr + 2, 0x02);
+            ppu.write_oam(addr + 3, 0x08 | 0x01);
+        }
+
+        ppu.tick(456, &mut interrupts);
+        let frame = ppu.framebuffer_rgb555();
+        assert_eq!(frame[0], 0x7FFF);  // palette 2 color 0 is still white
+        assert_eq!(frame[7], 0x001F);  // flipped pixel
+        assert_eq!(frame[8], 0x03E0);  // the background keeps its priority
+        assert_eq!(frame[16], 0x7C00);
+        assert_eq!(ppu.framebuffer()[16], 3);
+
+        // LCDC bit 0 clear: objects over everything, the background is still drawn
+        ppu.write(0xFF40, 0x92);
+        ppu.tick(456, &mut interrupts);
+        let frame = &ppu.framebuffer_rgb555()[SCREEN_WIDTH..];
+        assert_eq!(frame[7], 0x001F);
+        assert_eq!(frame[8], 0x7C00);
+    }
+}
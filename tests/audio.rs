@@ -0,0 +1,24 @@
+#[cfg(test)]
+mod test {
+    use utils::{
+        cartridge::CartContext,
+        cpu::CPU_CLOCK,
+        emu::Emulator,
+    };
+    #[test]
+    fn enable_audio() {
+        let mut emulator = Emulator::new(CartContext::new());
+
+        emulator.step();
+        assert!(emulator.take_samples().is_empty()); // disabled by default
+
+        emulator.enable_audio(48000);
+        let mut cycles = 0;
+        while cycles < CPU_CLOCK / 100 {
+            cycles += emulator.step();
+        }
+        let samples = emulator.take_samples();
+        assert!((2 * 480..=2 * 481).contains(&samples.len()));
+        assert!(emulator.take_samples().is_empty());
+    }
+}
@@ -0,0 +1,81 @@
+#[cfg(test)]
+mod test {
+    use utils::cartridge::{
+        CartContext,
+        error::{CartError, Validation},
+        header::CartridgeType,
+    };
+    use utils::emu::Emulator;
+
+    // blank 32 KiB ROM with valid checksums
+    fn rom(cart_type : u8) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0000] = 0x42;
+        rom[0x0147] = cart_type;
+        rom[0x014D] = rom[0x134..=0x14C].iter().fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1));
+        let global = rom.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+        rom[0x014E..=0x014F].copy_from_slice(&global.to_be_bytes());
+        rom
+    }
+
+    #[test]
+    fn from_bytes() {
+        let cartridge = CartContext::from_bytes(rom(0x00)).unwrap();
+        assert_eq!(cartridge.rom_size, 0x8000);
+        assert_eq!(cartridge.read(0x0000), 0x42); // mapped from offset 0
+
+        let cartridge = CartContext::from_reader(std::io::Cursor::new(rom(0x01))).unwrap();
+        assert_eq!(cartridge.header.cart_type, CartridgeType::Mbc1);
+
+        let reader = std::io::Cursor::new(vec![0u8; 0x8000]); // no checksums at all
+        assert!(CartContext::from_reader(reader.clone()).is_err());
+        assert!(CartContext::from_reader_with_validation(reader, Validation::Lenient).is_ok());
+    }
+    #[test]
+    fn errors() {
+        let result = CartContext::from_bytes_with_validation(vec![0u8; 0x100], Validation::Lenient);
+        assert!(matches!(result, Err(CartError::TooSmall(0x100))));
+
+        let mut bad = rom(0x00);
+        bad[0x014D] ^= 0xFF;
+        let result = CartContext::from_bytes_with_validation(bad.clone(), Validation::Strict);
+        assert!(matches!(result, Err(CartError::HeaderChecksumMismatch { .. })));
+        let cartridge = CartContext::from_bytes_with_validation(bad, Validation::Lenient).unwrap();
+        assert!(matches!(cartridge.warnings[..], [
+            CartError::HeaderChecksumMismatch { .. },
+            CartError::GlobalChecksumMismatch { .. }, // it covers the header checksum too
+        ]));
+
+        let result = CartContext::from_bytes(rom(0xFE));
+        assert!(matches!(result, Err(CartError::UnsupportedMapper(CartridgeType::HuC3))));
+
+        let mut short = rom(0x00);
+        short.truncate(0x4000);
+        let result = CartContext::from_bytes(short);
+        assert!(matches!(result, Err(CartError::SizeMismatch { header : 0x8000, actual : 0x4000 })));
+
+        // the global checksum is never enforced
+        let mut patched = rom(0x00);
+        patched[0x0200] = 0x01;
+        let cartridge = CartContext::from_bytes(patched).unwrap();
+        assert!(matches!(cartridge.warnings[..], [CartError::GlobalChecksumMismatch { .. }]));
+        assert!(CartContext::from_bytes(rom(0x00)).unwrap().warnings.is_empty());
+    }
+    #[test]
+    fn save_on_drop() {
+        let mut image = rom(0x03); // MBC1+RAM+BATTERY
+        image[0x0149] = 0x02;      // 8 KiB
+        let mut cartridge = CartContext::from_bytes_with_validation(image, Validation::Lenient).unwrap();
+
+        let path = std::env::temp_dir().join(format!("save_on_drop_{}.sav", std::process::id()));
+        cartridge.save_path = Some(path.clone());
+        cartridge.write(0x0000, 0x0A); // enable RAM
+        cartridge.write_ram(0xA000, 0x42);
+
+        drop(Emulator::new(cartridge));
+        let save = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[0], 0x42);
+    }
+}
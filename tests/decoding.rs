@@ -7,9 +7,32 @@ mod test {
         let (instruction, _) = InstructionType::from_byte(opcode).unwrap();
         instruction
     }
+    fn fetch_prefixed(opcode : u8) -> (InstructionType, u8) {
+        InstructionType::from_byte_prefixed(opcode).unwrap()
+    }
     #[test]
     fn decode() {
         assert_eq!(format!("{:?}", fetch(0x3E)), "Load(ByteReg(A), ByteConst)");
         assert_eq!(format!("{:?}", fetch(0x76)), "Halt");
+        assert_eq!(format!("{:?}", fetch(0x12)), "Load(Deref(WordReg(De)), ByteReg(A))");
+        assert_eq!(format!("{:?}", fetch(0x22)), "LoadI(Deref(WordReg(Hl)), ByteReg(A))");
+        assert_eq!(format!("{:?}", fetch(0xEF)), "Rst(40)");
+    }
+    #[test]
+    fn decode_prefixed() {
+        let (instruction, cycles) = fetch_prefixed(0x11);
+        assert_eq!(format!("{:?}", instruction), "Rl(ByteReg(C))");
+        assert_eq!(cycles, 8);
+
+        let (instruction, cycles) = fetch_prefixed(0x7E);
+        assert_eq!(format!("{:?}", instruction), "Bit(7, Deref(WordReg(Hl)))");
+        assert_eq!(cycles, 12);
+
+        let (instruction, cycles) = fetch_prefixed(0xC6);
+        assert_eq!(format!("{:?}", instruction), "Set(0, Deref(WordReg(Hl)))");
+        assert_eq!(cycles, 16);
+
+        // every CB opcode decodes
+        assert!((0..=0xFF).all(|op| InstructionType::from_byte_prefixed(op).is_some()));
     }
-}
\ No newline at end of file
+}
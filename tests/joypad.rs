@@ -0,0 +1,19 @@
+#[cfg(test)]
+mod test {
+    use utils::{
+        cartridge::CartContext,
+        emu::{Emulator, Button},
+        memory::Memory,
+    };
+    #[test]
+    fn set_button() {
+        let mut emulator = Emulator::new(CartContext::new());
+
+        emulator.cpu.mmu.set_byte(0xFF00, 0x20); // select direction buttons
+        emulator.set_button(Button::Down, true);
+        assert_eq!(emulator.cpu.mmu.fetch_byte(0xFF00) & 0x0F, 0x07);
+
+        emulator.set_button(Button::Down, false);
+        assert_eq!(emulator.cpu.mmu.fetch_byte(0xFF00) & 0x0F, 0x0F);
+    }
+}